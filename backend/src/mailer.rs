@@ -0,0 +1,108 @@
+//! Sending email to users.
+//!
+//! Email is sent through the `Mailer` trait so that development setups and
+//! tests don't need a mail server. If `smtp_host` is set in the config, mail is
+//! sent over SMTP; otherwise every email is written to a file instead.
+
+use crate::{generate_token, Error};
+
+use lettre::smtp::authentication::Credentials;
+use lettre::{SmtpClient, SmtpTransport, Transport};
+use lettre_email::EmailBuilder;
+use rocket::Config;
+
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Something that can deliver email
+pub(crate) trait Mailer: Send + Sync {
+    /// Sends an email with the given subject and plain text body to the given
+    /// address.
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), Error>;
+}
+
+/// Builds the mailer described by the given config.
+///
+/// `mail_dir` is where emails are written if no SMTP server is configured.
+pub(crate) fn from_config(config: &Config, mail_dir: &Path) -> Result<Box<dyn Mailer>, Error> {
+    let host = match config.get_str("smtp_host") {
+        Ok(host) => host,
+        Err(_) => return Ok(Box::new(FileMailer::new(mail_dir)))
+    };
+
+    let from = config.get_str("mail_from")
+        .map_err(|_| Error::MailErr("`mail_from` must be set when `smtp_host` is".to_string()))?;
+    let credentials = match (config.get_str("smtp_username"), config.get_str("smtp_password")) {
+        (Ok(username), Ok(password)) => Some(Credentials::new(username.to_string(), password.to_string())),
+        _ => None
+    };
+
+    Ok(Box::new(SmtpMailer::new(host, from, credentials)?))
+}
+
+/// Sends email through an SMTP server
+pub(crate) struct SmtpMailer {
+    from: String,
+    transport: Mutex<SmtpTransport>
+}
+
+impl SmtpMailer {
+    /// Creates a mailer that sends email from the given address through the
+    /// SMTP server at the given host, using TLS.
+    pub(crate) fn new(host: &str, from: &str, credentials: Option<Credentials>) -> Result<Self, Error> {
+        let mut client = SmtpClient::new_simple(host).map_err(|e| Error::MailErr(e.to_string()))?;
+        if let Some(credentials) = credentials {
+            client = client.credentials(credentials);
+        }
+
+        Ok(SmtpMailer {
+            from: from.to_string(),
+            transport: Mutex::new(client.transport())
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), Error> {
+        let email = EmailBuilder::new()
+            .to(to)
+            .from(self.from.as_str())
+            .subject(subject)
+            .text(body)
+            .build()
+            .map_err(|e| Error::MailErr(e.to_string()))?;
+
+        self.transport.lock().unwrap()
+            .send(email.into())
+            .map(|_| ())
+            .map_err(|e| Error::MailErr(e.to_string()))
+    }
+}
+
+/// Writes each email to its own file in a directory instead of sending it
+pub(crate) struct FileMailer {
+    dir: PathBuf
+}
+
+impl FileMailer {
+    /// Creates a mailer that writes emails to the given directory.
+    ///
+    /// The directory is created when the first email is written.
+    pub(crate) fn new<P: AsRef<Path>>(dir: P) -> Self {
+        FileMailer { dir: dir.as_ref().to_path_buf() }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), Error> {
+        create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("{}.eml", generate_token(16)));
+        let mut file = File::create(&path)?;
+        write!(file, "To: {}\r\nSubject: {}\r\n\r\n{}", to, subject, body)?;
+
+        info!("Wrote email to {} to {}", to, path.display());
+        Ok(())
+    }
+}
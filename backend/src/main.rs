@@ -1,12 +1,13 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 #[macro_use] extern crate rocket;
+#[macro_use] extern crate log;
 
 use rocket::outcome::Outcome;
 use rocket::http::Status;
-use rocket::response::{Content, NamedFile};
+use rocket::response::{self, NamedFile, Responder, Response};
 use rocket::response::status;
-use rocket::request::{self, FromRequest, Request};
-use rocket::http::{Cookie, Cookies, ContentType};
+use rocket::request::{self, Form, FromParam, FromRequest, Request};
+use rocket::http::{Cookie, Cookies, RawStr};
 use rocket::fairing::AdHoc;
 use rocket::Data;
 use rocket::State;
@@ -22,20 +23,39 @@ use tantivy::directory::MmapDirectory;
 use tantivy::{Index, ReloadPolicy, IndexWriter, IndexReader};
 
 use argonautica::{Hasher, Verifier};
-use chrono::{NaiveDateTime, Utc};
-use rusqlite::{params, Connection};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
 use serde_derive::{Deserialize, Serialize};
-use image::load_from_memory;
 
 use identicon_rs::{Identicon, ImageType};
+use rand::{thread_rng, Rng};
+use rand::distributions::Alphanumeric;
 
 use std::sync::Mutex;
 use std::path::Path;
 use std::io::Read;
 use std::fs::create_dir;
 
+mod mailer;
+mod media;
+mod metadata;
+mod session;
+use mailer::Mailer;
+use session::{ClientInfo, Session, SessionConfig, SessionDetails, SESSION_COOKIE};
+use media::{CachedFile, Crop, MediaStore, ProcessedImage, RenditionSize};
+
 type DbConn = Mutex<Connection>;
 struct ArgonSecretKey(String);
+/// The hash of a random password that logins for unknown emails are checked
+/// against, so that they take as long as logins for real users
+struct DummyHash(String);
+/// Whether or not signing up requires an invite code
+struct InviteOnly(bool);
+/// The URL the web client is served from, used to build links in emails
+struct PublicUrl(String);
+
+/// How long a password reset token can be used for
+const PASSWORD_RESET_EXPIRY_MINUTES: i64 = 60;
 
 #[cfg(feature = "deployable")]
 macro_rules! root_dir {
@@ -55,16 +75,28 @@ macro_rules! root_dir {
 #[derive(Debug)]
 enum Error {
     /// An error encountered while working with password hashes
-    HashError(argonautica::Error),
+    HashErr(argonautica::Error),
     DatabaseErr(rusqlite::Error),
     SearchErr(tantivy::Error),
     QueryParseErr(tantivy::query::QueryParserError),
     OpenDirectoryErr(tantivy::directory::error::OpenDirectoryError),
     IoErr(std::io::Error),
+    SerdeErr(serde_json::Error),
+    /// An error encountered while sending email
+    MailErr(String),
     /// Error returned when an attempt is made to create a new user with a real
     /// name or email address that already exists in the database.
     UserAlreadyExists,
     LoginFailed,
+    /// Error returned when the requested resource does not exist
+    NotFound,
+    /// Error returned when a user attempts to modify a resource they are not
+    /// allowed to modify
+    NotAuthorized,
+    /// Error returned when data provided by the client fails validation
+    ///
+    /// The string describes what was wrong with the input.
+    InvalidInput(&'static str),
     /// An error occured while trying to launch Rocket
     /// Placeholder error returned when failure to read uploaded image data occurs
     ImageUploadFailed
@@ -72,7 +104,7 @@ enum Error {
 
 impl From<argonautica::Error> for Error {
     fn from(err: argonautica::Error) -> Self {
-        Error::HashError(err)
+        Error::HashErr(err)
     }
 }
 
@@ -106,6 +138,51 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::SerdeErr(err)
+    }
+}
+
+impl<'r> Responder<'r> for Error {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
+        let (status, message) = match self {
+            Error::NotFound => (Status::NotFound, None),
+            Error::DatabaseErr(rusqlite::Error::QueryReturnedNoRows) => (Status::NotFound, None),
+            Error::NotAuthorized => (Status::Forbidden, None),
+            Error::LoginFailed => (Status::Unauthorized, None),
+            Error::UserAlreadyExists => (Status::Conflict, None),
+            Error::InvalidInput(message) => (Status::BadRequest, Some(message.to_string())),
+            Error::QueryParseErr(_) => (Status::BadRequest, None),
+            Error::ImageUploadFailed => (Status::BadRequest, None),
+            err => {
+                error!("Error while handling request: {:?}", err);
+                (Status::InternalServerError, None)
+            }
+        };
+
+        let body = ErrorResponse {
+            error: status.reason.to_string(),
+            message
+        };
+
+        Response::build_from(Json(body).respond_to(req)?)
+            .status(status)
+            .ok()
+    }
+}
+
+/// A resource that belongs to a single user.
+///
+/// Used with `User::authorize` to check whether or not a user may modify a
+/// resource.
+trait Owned {
+    /// Returns the id of the user that owns the resource with the given id
+    ///
+    /// Errors if the resource does not exist.
+    fn owner_id(conn: &Connection, id: u32) -> Result<u32, Error>;
+}
+
 /// Representation of a user in the database.
 // TODO: figure out a strategy for more precisely loading only the user data
 // that's really needed
@@ -130,30 +207,47 @@ struct User {
     ///
     /// This name can also change (obviously) but should be modified very rarely.
     real_name: String,
-    // The bytes of the PNG-encoded profile picture
-    profile_pic: Vec<u8>
+    /// The media store hash of the original profile picture
+    profile_pic_hash: String
 }
 
 impl<'a, 'r> FromRequest<'a, 'r> for User {
     type Error = Error;
 
     fn from_request(request: &'a Request<'r>) -> request::Outcome<User, Self::Error> {
+        let session = request.guard::<Session>()?;
         let db = request.guard::<State<DbConn>>().unwrap();
         let conn = db.lock().unwrap();
 
-        let res = request.cookies()
-            .get_private("user_id")
-            .and_then(|cookie| cookie.value().parse().ok())
-            .map(|id| User::load_id(&conn, id));
+        match User::load_id(&conn, session.user_id) {
+            Ok(r) => Outcome::Success(r),
+            Err(err) => Outcome::Failure((Status::InternalServerError, err))
+        }
+    }
+}
+
+/// A user that is allowed to administer the server
+///
+/// For now the first user to sign up is the only admin.
+struct Admin(User);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = Error;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Admin, Self::Error> {
+        let user = request.guard::<User>()?;
 
-        match res {
-            Some(Ok(r)) => Outcome::Success(r),
-            Some(Err(err)) => Outcome::Failure((Status::InternalServerError, err)),
-            None => Outcome::Forward(())
+        if user.user_id == 1 {
+            Outcome::Success(Admin(user))
+        } else {
+            Outcome::Failure((Status::Forbidden, Error::NotAuthorized))
         }
     }
 }
 
+/// The columns that `User::from_row` expects to be selected from the `user` table
+const USER_COLUMNS: &str = "user_id, hash, email, created_at, display_name, real_name, profile_pic_hash";
+
 impl User {
     /// Creates a table in the given database for storing this struct.
     ///
@@ -167,12 +261,25 @@ impl User {
                     created_at              TEXT NOT NULL,
                     display_name            TEXT NOT NULL,
                     real_name               TEXT NOT NULL,
-                    profile_pic             BLOB NOT NULL
+                    profile_pic_hash        TEXT NOT NULL
                     )",
             params![],
         ).map(|_| ())?)
     }
 
+    /// Builds a user from a row containing `USER_COLUMNS`
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(User {
+            user_id: row.get(0)?,
+            hash: row.get(1)?,
+            email: row.get(2)?,
+            created_at: row.get(3)?,
+            display_name: row.get(4)?,
+            real_name: row.get(5)?,
+            profile_pic_hash: row.get(6)?
+        })
+    }
+
     /// Checks to see if a user with the given email or real name exists and returns
     /// true if one does.
     fn exists(conn: &Connection, email: &str, real_name: &str) -> Result<bool, Error> {
@@ -191,43 +298,83 @@ impl User {
     ///
     /// Errors if the user cannot be created.
     // TODO: validate email server-side
-    fn create_new(conn: &Connection, rinfo: &RegisterInfo, key: &str) -> Result<(), Error> {
+    fn create_new(conn: &Connection, media: &MediaStore, rinfo: &RegisterInfo, key: &str) -> Result<(), Error> {
         if User::exists(&conn, &rinfo.email, &rinfo.real_name)? {
             return Err(Error::UserAlreadyExists);
         }
 
-        let mut hasher = Hasher::default();
-        let hash = hasher
-            .with_password(&rinfo.password)
-            .with_secret_key(key)
-            .hash()
-            .unwrap();
+        let hash = hash_password(&rinfo.password, key)?;
         let created_at = Utc::now().naive_utc();
 
-        let identicon = Identicon::new_default(&(rinfo.display_name.clone() + &rinfo.email + &rinfo.real_name));
-        let data = identicon.export_file_data(ImageType::PNG);
+        let profile_pic = User::identicon(&rinfo.display_name, &rinfo.email, &rinfo.real_name)?;
+        let profile_pic_hash = media.store_image(conn, &profile_pic)?;
 
         Ok(conn.execute(
-            "INSERT INTO user (hash, email, created_at, display_name, real_name, profile_pic)
+            "INSERT INTO user (hash, email, created_at, display_name, real_name, profile_pic_hash)
                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![hash, rinfo.email, created_at, rinfo.display_name, rinfo.real_name, data],
+            params![hash, rinfo.email, created_at, rinfo.display_name, rinfo.real_name, profile_pic_hash],
+        ).map(|_| ())?)
+    }
+
+    /// Generates the default profile picture for a user with the given details
+    fn identicon(display_name: &str, email: &str, real_name: &str) -> Result<ProcessedImage, Error> {
+        let identicon = Identicon::new_default(&(display_name.to_string() + email + real_name));
+        ProcessedImage::new(identicon.export_file_data(ImageType::PNG))
+    }
+
+    /// Applies the given changes to this user's profile.
+    ///
+    /// Errors if another user already has the requested real name.
+    fn update_profile(&mut self, conn: &Connection, update: &ProfileUpdateInfo) -> Result<(), Error> {
+        if let Some(display_name) = &update.display_name {
+            if display_name.trim().is_empty() {
+                return Err(Error::InvalidInput("display name cannot be empty"));
+            }
+
+            self.display_name = display_name.clone();
+        }
+
+        if let Some(real_name) = &update.real_name {
+            if real_name.trim().is_empty() {
+                return Err(Error::InvalidInput("real name cannot be empty"));
+            }
+
+            let mut stmt = conn.prepare("SELECT * FROM user WHERE real_name=?1 AND user_id!=?2")?;
+            if stmt.exists(params![real_name, self.user_id])? {
+                return Err(Error::UserAlreadyExists);
+            }
+
+            self.real_name = real_name.clone();
+        }
+
+        if let Some(details) = &update.details {
+            details.save(conn, self.user_id)?;
+        }
+
+        Ok(conn.execute(
+            "UPDATE user SET display_name=?1, real_name=?2 WHERE user_id=?3",
+            params![self.display_name, self.real_name, self.user_id],
         ).map(|_| ())?)
     }
 
+    /// Replaces this user's profile picture with the given image.
+    fn set_profile_pic(&mut self, conn: &Connection, media: &MediaStore, image: &ProcessedImage) -> Result<(), Error> {
+        // the hash is loaded again in case it changed since this user was loaded
+        let old_hash = User::profile_pic_hash(conn, self.user_id)?;
+        self.profile_pic_hash = media.store_image(conn, image)?;
+
+        conn.execute(
+            "UPDATE user SET profile_pic_hash=?1 WHERE user_id=?2",
+            params![self.profile_pic_hash, self.user_id],
+        )?;
+
+        media.release(conn, &old_hash)
+    }
+
     /// Loads and returns all users
     fn load_all(conn: &Connection) -> Result<Vec<Self>, Error> {
-        let mut stmt = conn.prepare("SELECT user_id, hash, email, created_at, display_name, real_name, profile_pic FROM user")?;
-        let user_iter = stmt.query_map(params![], |row| {
-            Ok(User {
-                user_id: row.get(0)?,
-                hash: row.get(1)?,
-                email: row.get(2)?,
-                created_at: row.get(3)?,
-                display_name: row.get(4)?,
-                real_name: row.get(5)?,
-                profile_pic: row.get(6)?
-            })
-        })?;
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM user", USER_COLUMNS))?;
+        let user_iter = stmt.query_map(params![], User::from_row)?;
 
         user_iter.map(|res| match res {
             Ok(v) => Ok(v),
@@ -238,59 +385,65 @@ impl User {
     /// Loads the user specified by the given id from the database.
     fn load_id(conn: &Connection, user_id: u32) -> Result<Self, Error> {
         Ok(conn.query_row(
-            "SELECT user_id, hash, email, created_at, display_name, real_name, profile_pic FROM user WHERE user_id=?1",
+            &format!("SELECT {} FROM user WHERE user_id=?1", USER_COLUMNS),
             params![user_id],
-            |row| {
-                Ok(User {
-                    user_id: row.get(0)?,
-                    hash: row.get(1)?,
-                    email: row.get(2)?,
-                    created_at: row.get(3)?,
-                    display_name: row.get(4)?,
-                    real_name: row.get(5)?,
-                    profile_pic: row.get(6)?
-                })
-            }
+            User::from_row
         )?)
     }
 
     /// Loads the user specified by the given email from the database
     fn load_email(conn: &Connection, email: &str) -> Result<Self, Error> {
         Ok(conn.query_row(
-            "SELECT user_id, hash, email, created_at, display_name, real_name, profile_pic FROM user WHERE email=?1",
+            &format!("SELECT {} FROM user WHERE email=?1", USER_COLUMNS),
             params![email],
-            |row| {
-                Ok(User {
-                    user_id: row.get(0)?,
-                    hash: row.get(1)?,
-                    email: row.get(2)?,
-                    created_at: row.get(3)?,
-                    display_name: row.get(4)?,
-                    real_name: row.get(5)?,
-                    profile_pic: row.get(6)?
-                })
-            }
+            User::from_row
         )?)
     }
 
-    /// Returns a buffer with the profile pic for the user specified by the given id
-    // TODO: right now images are loaded into memory in their entirety from the database
-    //
-    // this might not be an issue as this server will never deal with large images buuuut
-    // it might be nice to fix regardless
-    //
-    // might be better to store images outside the database in the filesystem to avoid
-    // fighting with the sqlite API
-    fn get_profile_pic(conn: &Connection, user_id: u32) -> Result<Vec<u8>, Error> {
+    /// Returns the media store hash of the original profile pic for the user
+    /// specified by the given id
+    fn profile_pic_hash(conn: &Connection, user_id: u32) -> Result<String, Error> {
         Ok(conn.query_row(
-            "SELECT profile_pic FROM user WHERE user_id=?1",
+            "SELECT profile_pic_hash FROM user WHERE user_id=?1",
             params![user_id],
-            |row| {
-                Ok(row.get(0)?)
-            }
+            |row| row.get(0)
         )?)
     }
 
+    /// Returns an error unless this user may modify the resource of type `T`
+    /// with the given id.
+    ///
+    /// Every route that modifies an existing resource should check this before
+    /// doing so.
+    fn authorize<T: Owned>(&self, conn: &Connection, id: u32) -> Result<(), Error> {
+        if T::owner_id(conn, id)? == self.user_id {
+            Ok(())
+        } else {
+            Err(Error::NotAuthorized)
+        }
+    }
+
+    /// Returns true if the given password is this user's password
+    fn verify_password(&self, password: &str, key: &str) -> Result<bool, Error> {
+        verify_password(&self.hash, password, key)
+    }
+
+    /// Replaces this user's password with the given one.
+    ///
+    /// The `key` parameter is the secret key given to argon for hashing
+    fn set_password(&mut self, conn: &Connection, password: &str, key: &str) -> Result<(), Error> {
+        if password.is_empty() {
+            return Err(Error::InvalidInput("password cannot be empty"));
+        }
+
+        self.hash = hash_password(password, key)?;
+
+        Ok(conn.execute(
+            "UPDATE user SET hash=?1 WHERE user_id=?2",
+            params![self.hash, self.user_id],
+        ).map(|_| ())?)
+    }
+
     /// Returns true if this user matches the given `LoginInfo`
     ///
     /// This means that the emails are equivalent and the password the user
@@ -298,15 +451,235 @@ impl User {
     ///
     /// The `key` parameter is the secret key given to argon for hashing
     fn auth(&self, login_info: &LoginInfo, key: &str) -> Result<bool, Error> {
-        let mut verifier = Verifier::default();
-        Ok(
-            login_info.email == self.email &&
-            verifier
-                .with_hash(&self.hash)
-                .with_password(&login_info.password)
-                .with_secret_key(key)
-                .verify()?
-        )
+        Ok(login_info.email == self.email && self.verify_password(&login_info.password, key)?)
+    }
+}
+
+/// The most links a user can put on their profile
+const MAX_PROFILE_LINKS: usize = 10;
+
+/// Who is allowed to see a profile field
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Visibility {
+    /// Everyone with an account on the server
+    Members,
+    /// Only the user the profile belongs to
+    OnlyMe
+}
+
+impl Visibility {
+    fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Members => "members",
+            Visibility::OnlyMe => "only_me"
+        }
+    }
+
+    fn from_str(visibility: &str) -> Self {
+        match visibility {
+            "members" => Visibility::Members,
+            _ => Visibility::OnlyMe
+        }
+    }
+}
+
+/// A profile field's value along with who can see it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct ProfileField<T> {
+    value: T,
+    visibility: Visibility
+}
+
+/// A user's birthday
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Birthday {
+    /// Only shown to other users if `hide_year` is false
+    year: Option<i32>,
+    month: u32,
+    day: u32,
+    hide_year: bool
+}
+
+/// The optional parts of a user's profile
+///
+/// Fields the viewer isn't allowed to see are left out.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+struct ProfileDetails {
+    bio: Option<ProfileField<String>>,
+    birthday: Option<ProfileField<Birthday>>,
+    hometown: Option<ProfileField<String>>,
+    pronouns: Option<ProfileField<String>>,
+    links: Option<ProfileField<Vec<String>>>,
+    /// Who can see the user's cover photo, if they have one
+    ///
+    /// The cover photo itself is set with its own route.
+    cover_photo: Option<Visibility>
+}
+
+impl ProfileDetails {
+    /// Creates a table in the given database for storing profile fields.
+    ///
+    /// The table will only be created if it does not already exist.
+    fn create_table(conn: &Connection) -> Result<(), Error> {
+        Ok(conn.execute(
+            "CREATE TABLE if not exists profile_field (
+                    user_id                 INTEGER NOT NULL,
+                    name                    TEXT NOT NULL,
+                    value                   TEXT NOT NULL,
+                    visibility              TEXT NOT NULL,
+                    PRIMARY KEY (user_id, name)
+                    )",
+            params![],
+        ).map(|_| ())?)
+    }
+
+    /// Loads the profile details of the given user as seen by the given viewer.
+    fn load(conn: &Connection, user_id: u32, viewer_id: u32) -> Result<Self, Error> {
+        fn field<T: serde::de::DeserializeOwned>(value: &str, visibility: Visibility) -> Result<Option<ProfileField<T>>, Error> {
+            Ok(Some(ProfileField { value: serde_json::from_str(value)?, visibility }))
+        }
+
+        let mut stmt = conn.prepare("SELECT name, value, visibility FROM profile_field WHERE user_id=?1")?;
+        let field_iter = stmt.query_map(params![user_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+        })?;
+
+        let mut details = ProfileDetails::default();
+        for row in field_iter {
+            let (name, value, visibility) = row?;
+            let visibility = Visibility::from_str(&visibility);
+
+            if visibility == Visibility::OnlyMe && viewer_id != user_id {
+                continue;
+            }
+
+            match name.as_str() {
+                "bio" => details.bio = field(&value, visibility)?,
+                "birthday" => details.birthday = field(&value, visibility)?,
+                "hometown" => details.hometown = field(&value, visibility)?,
+                "pronouns" => details.pronouns = field(&value, visibility)?,
+                "links" => details.links = field(&value, visibility)?,
+                "cover_photo" => details.cover_photo = Some(visibility),
+                _ => {}
+            }
+        }
+
+        if let Some(birthday) = &mut details.birthday {
+            if birthday.value.hide_year && viewer_id != user_id {
+                birthday.value.year = None;
+            }
+        }
+
+        Ok(details)
+    }
+
+    /// Checks that the details make sense, returning an error if they don't.
+    fn validate(&self) -> Result<(), Error> {
+        if let Some(birthday) = &self.birthday {
+            // 2000 was a leap year, so any real month and day is valid in it
+            let year = birthday.value.year.unwrap_or(2000);
+            if NaiveDate::from_ymd_opt(year, birthday.value.month, birthday.value.day).is_none() {
+                return Err(Error::InvalidInput("birthday is not a valid date"));
+            }
+        }
+
+        if let Some(links) = &self.links {
+            if links.value.len() > MAX_PROFILE_LINKS {
+                return Err(Error::InvalidInput("too many links"));
+            }
+
+            if !links.value.iter().all(|link| link.starts_with("https://") || link.starts_with("http://")) {
+                return Err(Error::InvalidInput("links must be http or https URLs"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the profile details of the given user with these ones.
+    ///
+    /// The cover photo is left alone apart from its visibility.
+    fn save(&self, conn: &Connection, user_id: u32) -> Result<(), Error> {
+        fn encode<T: serde::Serialize>(field: &Option<ProfileField<T>>) -> Result<Option<(String, Visibility)>, Error> {
+            field.as_ref()
+                .map(|field| Ok((serde_json::to_string(&field.value)?, field.visibility)))
+                .transpose()
+        }
+
+        self.validate()?;
+
+        conn.execute("DELETE FROM profile_field WHERE user_id=?1 AND name!='cover_photo'", params![user_id])?;
+
+        let mut stmt = conn.prepare(
+            "INSERT INTO profile_field (user_id, name, value, visibility) VALUES (?1, ?2, ?3, ?4)"
+        )?;
+        let mut insert = |name: &str, value: Option<(String, Visibility)>| -> Result<(), Error> {
+            if let Some((value, visibility)) = value {
+                stmt.execute(params![user_id, name, value, visibility.as_str()])?;
+            }
+
+            Ok(())
+        };
+
+        insert("bio", encode(&self.bio)?)?;
+        insert("birthday", encode(&self.birthday)?)?;
+        insert("hometown", encode(&self.hometown)?)?;
+        insert("pronouns", encode(&self.pronouns)?)?;
+        insert("links", encode(&self.links)?)?;
+
+        if let Some(visibility) = self.cover_photo {
+            conn.execute(
+                "UPDATE profile_field SET visibility=?1 WHERE user_id=?2 AND name='cover_photo'",
+                params![visibility.as_str(), user_id],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the media store hash of the given user's cover photo if the
+    /// given viewer is allowed to see it.
+    fn cover_photo_hash(conn: &Connection, user_id: u32, viewer_id: u32) -> Result<Option<String>, Error> {
+        let cover_photo: Option<(String, String)> = conn.query_row(
+            "SELECT value, visibility FROM profile_field WHERE user_id=?1 AND name='cover_photo'",
+            params![user_id],
+            |row| Ok((row.get(0)?, row.get(1)?))
+        ).optional()?;
+
+        Ok(match cover_photo {
+            Some((hash, visibility)) if viewer_id == user_id || Visibility::from_str(&visibility) == Visibility::Members => {
+                Some(serde_json::from_str(&hash)?)
+            },
+            _ => None
+        })
+    }
+
+    /// Sets the given user's cover photo to the given image, or removes it if
+    /// no image is given.
+    ///
+    /// A new cover photo keeps the visibility of the one it replaces.
+    fn set_cover_photo(conn: &Connection, media: &MediaStore, user_id: u32, image: Option<&ProcessedImage>) -> Result<(), Error> {
+        let old_hash = ProfileDetails::cover_photo_hash(conn, user_id, user_id)?;
+
+        match image {
+            Some(image) => {
+                let hash = media.store_image(conn, image)?;
+                conn.execute(
+                    "INSERT INTO profile_field (user_id, name, value, visibility) VALUES (?1, 'cover_photo', ?2, ?3)
+                        ON CONFLICT (user_id, name) DO UPDATE SET value=excluded.value",
+                    params![user_id, serde_json::to_string(&hash)?, Visibility::Members.as_str()],
+                )?;
+            },
+            None => {
+                conn.execute("DELETE FROM profile_field WHERE user_id=?1 AND name='cover_photo'", params![user_id])?;
+            }
+        }
+
+        match old_hash {
+            Some(old_hash) => media.release(conn, &old_hash),
+            None => Ok(())
+        }
     }
 }
 
@@ -316,27 +689,50 @@ struct Post {
     id: u32,
     body: String,
     created_at: NaiveDateTime,
-    // TODO: how to differentiate between png / jpeg?
-    image: Option<Vec<u8>>,
+    /// When the body of this post was last changed, if ever
+    edited_at: Option<NaiveDateTime>,
     /// The user that made this post
-    user_id: u32
+    user_id: u32,
+    /// When the photos in this post were taken, if the user chose to share it
+    taken_at: Option<NaiveDateTime>
 }
 
 impl Post {
     /// Creates a table in the given database for storing this struct.
     ///
-    /// The table will only be created if it does not already exist.
+    /// The table will only be created if it does not already exist. Columns
+    /// added since the table was first created are added to it if missing.
     fn create_table(conn: &Connection) -> Result<(), Error> {
-        Ok(conn.execute(
+        conn.execute(
             "CREATE TABLE if not exists post (
                     id                      INTEGER PRIMARY KEY,
                     body                    TEXT NOT NULL,
                     created_at              TEXT NOT NULL,
-                    image                   BLOB,
-                    user_id                 INTEGER
+                    user_id                 INTEGER,
+                    edited_at               TEXT,
+                    taken_at                TEXT
                     )",
             params![],
-        ).map(|_| ())?)
+        )?;
+
+        add_column_if_missing(conn, "post", "edited_at", "TEXT")?;
+        add_column_if_missing(conn, "post", "taken_at", "TEXT")
+    }
+
+    /// Builds the search index document for a post.
+    fn search_document(schema: &Schema, post_id: u32, user_id: u32, body: &str, created_at: DateTime<Utc>) -> Document {
+        let body_field = schema.get_field("body").unwrap();
+        let post_id_field = schema.get_field("post_id").unwrap();
+        let user_id_field = schema.get_field("user_id").unwrap();
+        let created_at_field = schema.get_field("created_at").unwrap();
+
+        doc!(
+            body_field => body.to_string(),
+            post_id_field => post_id as u64,
+            user_id_field => user_id as u64,
+            // TODO: is inserting datetime here rather than naivedatetime going to cause issues?
+            created_at_field => created_at
+        )
     }
 
     /// Inserts a new post into the database based on the given post info and user id.
@@ -351,10 +747,6 @@ impl Post {
         user_id: u32
     ) -> Result<(), Error> {
         let created_at = Utc::now();
-        let body_field = schema.get_field("body").unwrap();
-        let post_id_field = schema.get_field("post_id").unwrap();
-        let user_id_field = schema.get_field("user_id").unwrap();
-        let created_at_field = schema.get_field("created_at").unwrap();
 
         conn.execute(
             "INSERT INTO post (body, created_at, user_id)
@@ -362,12 +754,12 @@ impl Post {
             params![pinfo.body, created_at.naive_utc(), user_id],
         )?;
 
-        index_writer.add_document(doc!(
-            body_field => pinfo.body.clone(),
-            post_id_field => conn.last_insert_rowid() as u64,
-            user_id_field => user_id as u64,
-            // TODO: is inserting datetime here rather than naivedatetime going to cause issues?
-            created_at_field => created_at
+        index_writer.add_document(Post::search_document(
+            schema,
+            conn.last_insert_rowid() as u32,
+            user_id,
+            &pinfo.body,
+            created_at
         ));
 
         index_writer.commit()?;
@@ -375,785 +767,3524 @@ impl Post {
         Ok(())
     }
 
-    /// Set the image of the post specified by the given id to the given image data.
-    // TODO: this should support streaming so the entire image doesn't have to be
-    // loaded in memory
-    fn set_image(conn: &Connection, post_id: u32, data: &[u8]) -> Result<(), Error> {
-        Ok(conn.execute(
-            "UPDATE post SET image=?1 WHERE id=?2",
-            params![data, post_id],
-        ).map(|_| ())?)
+    /// Replaces the body of the post specified by the given id, recording when
+    /// the edit happened.
+    ///
+    /// The post's search document is replaced once the database has been
+    /// updated, so the index never has changes the database doesn't.
+    fn update(
+        conn: &mut Connection,
+        index_writer: &mut IndexWriter,
+        schema: &Schema,
+        post_id: u32,
+        pinfo: &PostInfo
+    ) -> Result<(), Error> {
+        let post = Post::load_id(conn, post_id)?;
+        let post_id_field = schema.get_field("post_id").unwrap();
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "UPDATE post SET body=?1, edited_at=?2 WHERE id=?3",
+            params![pinfo.body, Utc::now().naive_utc(), post_id],
+        )?;
+
+        index_writer.delete_term(Term::from_field_u64(post_id_field, post_id as u64));
+        index_writer.add_document(Post::search_document(
+            schema,
+            post_id,
+            post.user_id,
+            &pinfo.body,
+            DateTime::from_utc(post.created_at, Utc)
+        ));
+
+        if let Err(e) = tx.commit() {
+            let _ = index_writer.rollback();
+            return Err(e.into());
+        }
+
+        index_writer.commit()?;
+        Ok(())
     }
 
-    // Get the image for the specified post id
-    fn get_image(conn: &Connection, post_id: u32) -> Result<Vec<u8>, Error> {
-        Ok(conn.query_row(
-            "SELECT image FROM post WHERE id=?1",
+    /// Deletes the post specified by the given id along with its images,
+    /// comments and reactions.
+    ///
+    /// The post's search document is removed once the database has been
+    /// updated, so the index never has changes the database doesn't.
+    fn delete(
+        conn: &mut Connection,
+        index_writer: &mut IndexWriter,
+        schema: &Schema,
+        media: &MediaStore,
+        post_id: u32
+    ) -> Result<(), Error> {
+        let post_id_field = schema.get_field("post_id").unwrap();
+        let image_hashes = PostImage::hashes_for_post(conn, post_id)?;
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "DELETE FROM reaction WHERE target_kind='comment'
+                    AND target_id IN (SELECT id FROM comment WHERE post_id=?1)",
             params![post_id],
-            |row| {
-                Ok(row.get(0)?)
-            }
-        )?)
+        )?;
+        tx.execute(
+            "DELETE FROM reaction WHERE target_kind='post' AND target_id=?1",
+            params![post_id],
+        )?;
+        tx.execute("DELETE FROM comment WHERE post_id=?1", params![post_id])?;
+        tx.execute("DELETE FROM post_image WHERE post_id=?1", params![post_id])?;
+        tx.execute("DELETE FROM post WHERE id=?1", params![post_id])?;
+
+        index_writer.delete_term(Term::from_field_u64(post_id_field, post_id as u64));
+
+        if let Err(e) = tx.commit() {
+            let _ = index_writer.rollback();
+            return Err(e.into());
+        }
+
+        index_writer.commit()?;
+
+        // files are only released once the images are definitely gone so that a
+        // failed deletion can't leave images pointing at missing files
+        for hash in image_hashes {
+            media.release(conn, &hash)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if a post with the given id exists
+    fn exists(conn: &Connection, post_id: u32) -> Result<bool, Error> {
+        let mut stmt = conn.prepare("SELECT id FROM post WHERE id=?1")?;
+        Ok(stmt.exists(params![post_id])?)
     }
 
     /// Loads the post specified by the given id from the database.
     fn load_id(conn: &Connection, post_id: u32) -> Result<Self, Error> {
         Ok(conn.query_row(
-            "SELECT id, body, created_at, image, user_id FROM post WHERE id=?1",
+            "SELECT id, body, created_at, edited_at, user_id, taken_at FROM post WHERE id=?1",
             params![post_id],
             |row| {
                 Ok(Post {
                     id: row.get(0)?,
                     body: row.get(1)?,
                     created_at: row.get(2)?,
-                    image: row.get(3)?,
-                    user_id: row.get(4)?
+                    edited_at: row.get(3)?,
+                    user_id: row.get(4)?,
+                    taken_at: row.get(5)?
                 })
             }
         )?)
     }
 
+    /// Records when the photos in the post specified by the given id were taken
+    fn set_taken_at(conn: &Connection, post_id: u32, taken_at: NaiveDateTime) -> Result<(), Error> {
+        Ok(conn.execute(
+            "UPDATE post SET taken_at=?1 WHERE id=?2",
+            params![taken_at, post_id],
+        ).map(|_| ())?)
+    }
+
+    /// Loads the details of the post specified by the given id from the database.
+    ///
+    /// `viewer_id` is the id of the user the details are being loaded for.
+    fn load_info(conn: &Connection, post_id: u32, viewer_id: u32) -> Result<PostDetails, Error> {
+        let mut post = conn.query_row(
+            &format!("SELECT {} FROM post WHERE id=?1", POST_DETAILS_COLUMNS),
+            params![post_id],
+            PostDetails::from_row
+        )?;
+        post.load_attachments(conn, viewer_id)?;
+
+        Ok(post)
+    }
+
     /// Load `num` number of the most recent posts.
     /// 
     /// If a user id is provided, the posts will be the recent posts from that
     /// user only. If no id is provided then the most recent posts globally
     /// will be returned.
+    ///
+    /// `viewer_id` is the id of the user the details are being loaded for.
     // TODO: write a test to ensure that this fn works with and without a user id
-    fn load_recents_info(conn: &Connection, num: u32, user_id: Option<u32>) -> Result<Vec<PostDetails>, Error> {
+    fn load_recents_info(conn: &Connection, num: u32, user_id: Option<u32>, viewer_id: u32) -> Result<Vec<PostDetails>, Error> {
         let mut stmt;
         let params;
         let user_id = user_id.unwrap_or(0);
         
         if user_id > 0 {
-            stmt = conn.prepare("SELECT id, body, created_at, user_id, image FROM post WHERE user_id=?2 ORDER BY id DESC LIMIT ?1")?;
+            stmt = conn.prepare(&format!("SELECT {} FROM post WHERE user_id=?2 ORDER BY id DESC LIMIT ?1", POST_DETAILS_COLUMNS))?;
             params = params![num, user_id].to_vec();
         } else {
-            stmt = conn.prepare("SELECT id, body, created_at, user_id, image FROM post ORDER BY id DESC LIMIT ?1")?;
+            stmt = conn.prepare(&format!("SELECT {} FROM post ORDER BY id DESC LIMIT ?1", POST_DETAILS_COLUMNS))?;
             params = params![num].to_vec();
         }
         
-        let post_iter = stmt.query_map(params, |row| {
-            let image: Option<Vec<u8>> = row.get(4)?;
-
-            Ok(PostDetails {
-                id: row.get(0)?,
-                body: row.get(1)?,
-                created_at: row.get::<_, NaiveDateTime>(2)?.timestamp(),
-                user_id: row.get(3)?,
-                has_image: image.is_some()
-            })
-        })?;
-
-        post_iter.map(|res| match res {
+        let post_iter = stmt.query_map(params, PostDetails::from_row)?;
+        let mut posts = post_iter.map(|res| match res {
             Ok(v) => Ok(v),
             Err(e) => Err(Error::from(e))
-        }).collect()
+        }).collect::<Result<Vec<_>, Error>>()?;
+
+        for post in &mut posts {
+            post.load_attachments(conn, viewer_id)?;
+        }
+
+        Ok(posts)
     }
 }
 
-/// Web client posts this to create a new user
-#[derive(Serialize, Deserialize)]
-struct RegisterInfo {
-    email: String,
-    password: String,
-    display_name: String,
-    real_name: String
+impl Owned for Post {
+    fn owner_id(conn: &Connection, post_id: u32) -> Result<u32, Error> {
+        Ok(conn.query_row(
+            "SELECT user_id FROM post WHERE id=?1",
+            params![post_id],
+            |row| row.get(0)
+        )?)
+    }
 }
 
-/// Web client posts this to create a new post
-#[derive(Serialize, Deserialize)]
-struct PostInfo {
-    body: String
-}
+/// The maximum number of images a single post can have
+const MAX_POST_IMAGES: u32 = 20;
 
-/// Web client receives this to display posts
-#[derive(Serialize, Deserialize)]
-struct PostDetails {
+/// Representation of one of the images in a post's ordered set of images
+#[derive(Debug, PartialEq)]
+struct PostImage {
     id: u32,
-    body: String,
-    created_at: i64,
-    user_id: u32,
-    has_image: bool
+    /// The post this image belongs to
+    post_id: u32,
+    /// Where this image appears in the post's set of images, starting at 0
+    position: u32,
+    /// The media store hash of the original image data
+    hash: String,
+    created_at: NaiveDateTime
 }
 
-impl From<Post> for PostDetails {
-    fn from(post: Post) -> Self {
-        Self {
-            id: post.id,
-            body: post.body,
-            created_at: post.created_at.timestamp(),
-            user_id: post.user_id,
-            has_image: post.image.is_some()
+impl PostImage {
+    /// Creates a table in the given database for storing this struct.
+    ///
+    /// The table will only be created if it does not already exist.
+    fn create_table(conn: &Connection) -> Result<(), Error> {
+        conn.execute(
+            "CREATE TABLE if not exists post_image (
+                    id                      INTEGER PRIMARY KEY AUTOINCREMENT,
+                    post_id                 INTEGER NOT NULL,
+                    position                INTEGER NOT NULL,
+                    hash                    TEXT NOT NULL,
+                    created_at              TEXT NOT NULL
+                    )",
+            params![],
+        )?;
+
+        // ids used to be reused after an image was removed, but browsers cache
+        // images by id forever. Tables that still have images stored in them
+        // are rebuilt when those are moved to the media store instead.
+        let sql: String = conn.query_row(
+            "SELECT sql FROM sqlite_master WHERE type='table' AND name='post_image'",
+            params![],
+            |row| row.get(0)
+        )?;
+        if !sql.contains("AUTOINCREMENT") && !column_exists(conn, "post_image", "data")? {
+            rebuild_table(conn, "post_image", "id, post_id, position, hash, created_at", PostImage::create_table)?;
         }
-    }
-}
 
-/// Web client receives this after creating a post
-#[derive(Serialize, Deserialize)]
-struct PostCreationResponse {
-    post_id: u32
-}
+        Ok(())
+    }
 
-/// Web client posts this to login.
-#[derive(Serialize, Deserialize)]
-struct LoginInfo {
-    email: String,
-    password: String
-}
+    /// Adds the given image to the end of the given post's set of images.
+    ///
+    /// Returns the id of the new image.
+    fn add(conn: &Connection, media: &MediaStore, post_id: u32, image: &ProcessedImage) -> Result<u32, Error> {
+        if !Post::exists(conn, post_id)? {
+            return Err(Error::NotFound);
+        }
 
-/// Information about the requested user
-// TODO: reorganize this whole file so intent is clearer
-#[derive(Serialize, Deserialize)]
-struct UserInfo {
-    user_id: u32,
-    display_name: String,
-    real_name: String
-}
+        let count: u32 = conn.query_row(
+            "SELECT COUNT(*) FROM post_image WHERE post_id=?1",
+            params![post_id],
+            |row| row.get(0)
+        )?;
 
-impl From<User> for UserInfo {
-    fn from(user: User) -> Self {
-        Self {
-            user_id: user.user_id,
-            display_name: user.display_name,
-            real_name: user.real_name
+        if count >= MAX_POST_IMAGES {
+            return Err(Error::InvalidInput("this post already has the maximum number of images"));
         }
-    }
-}
 
-/// Returns information about the requested user id
-#[get("/user-info/<req_user_id>")]
-fn user_info(_user: User, db: State<DbConn>, req_user_id: u32) -> Result<Json<UserInfo>, Error> {
-    let conn = db.lock().unwrap();
-    let user = User::load_id(&conn, req_user_id)?;
+        let hash = media.store_image(conn, image)?;
+        conn.execute(
+            "INSERT INTO post_image (post_id, position, hash, created_at)
+                    VALUES (?1, ?2, ?3, ?4)",
+            params![post_id, count, hash, Utc::now().naive_utc()],
+        )?;
 
-    Ok(Json(user.into()))
-}
+        Ok(conn.last_insert_rowid() as u32)
+    }
 
-/// Returns information about all registered users
-#[get("/users")]
-fn users(_user: User, db: State<DbConn>) -> Result<Json<Vec<UserInfo>>, Error> {
-    let conn = db.lock().unwrap();
-    let users = User::load_all(&conn)?;
+    /// Loads the image specified by the given id from the database.
+    fn load_id(conn: &Connection, image_id: u32) -> Result<Self, Error> {
+        Ok(conn.query_row(
+            "SELECT id, post_id, position, hash, created_at FROM post_image WHERE id=?1",
+            params![image_id],
+            |row| {
+                Ok(PostImage {
+                    id: row.get(0)?,
+                    post_id: row.get(1)?,
+                    position: row.get(2)?,
+                    hash: row.get(3)?,
+                    created_at: row.get(4)?
+                })
+            }
+        )?)
+    }
 
-    Ok(Json(users.into_iter().map(|u| u.into()).collect()))
-}
+    /// Returns the ids of the given post's images in order.
+    fn ids_for_post(conn: &Connection, post_id: u32) -> Result<Vec<u32>, Error> {
+        let mut stmt = conn.prepare("SELECT id FROM post_image WHERE post_id=?1 ORDER BY position ASC")?;
+        let id_iter = stmt.query_map(params![post_id], |row| row.get(0))?;
 
-/// Returns details about recent n recent posts for the given user id
-// TODO: use query parameters more, they're more idiomatic
-// also show up nicer in the network panel
-#[get("/recent-posts?<req_user_id>&<n>")]
-fn recent_posts(_user: User, db: State<DbConn>, req_user_id: Option<u32>, n: u32) -> Result<Json<Vec<PostDetails>>, Error> {
-    let conn = db.lock().unwrap();
-    let posts = Post::load_recents_info(&conn, n, req_user_id)?;
+        id_iter.map(|res| match res {
+            Ok(v) => Ok(v),
+            Err(e) => Err(Error::from(e))
+        }).collect()
+    }
 
-    Ok(Json(posts))
-}
+    /// Returns the media store hashes of the given post's images.
+    fn hashes_for_post(conn: &Connection, post_id: u32) -> Result<Vec<String>, Error> {
+        let mut stmt = conn.prepare("SELECT hash FROM post_image WHERE post_id=?1")?;
+        let hash_iter = stmt.query_map(params![post_id], |row| row.get(0))?;
 
-/// Searches post bodies using the given query and returns the top 10 results
-#[get("/search-posts?<query_string>")]
-fn search_posts(
-    _user: User,
-    db: State<DbConn>,
-    reader: State<IndexReader>,
-    schema: State<Schema>,
-    query_string: String
-) -> Result<Json<Vec<PostDetails>>, Error> {
-    let conn = db.lock().unwrap();
-    let searcher = reader.searcher();
-    let body_field = schema.get_field("body").unwrap();
-    let post_id_field = schema.get_field("post_id").unwrap();
-    let query_parser = QueryParser::for_index(searcher.index(), vec![body_field]);
-    let mut posts = vec![];
+        hash_iter.map(|res| match res {
+            Ok(v) => Ok(v),
+            Err(e) => Err(Error::from(e))
+        }).collect()
+    }
 
-    let query = query_parser.parse_query(&query_string)?;
-    let top_docs = searcher.search(&query, &TopDocs::with_limit(10))?;
+    /// Rearranges the given post's images into the given order.
+    ///
+    /// `image_ids` must contain the id of every image in the post exactly once.
+    fn reorder(conn: &Connection, post_id: u32, image_ids: &[u32]) -> Result<(), Error> {
+        let mut current_ids = PostImage::ids_for_post(conn, post_id)?;
+        let mut new_ids = image_ids.to_vec();
+        current_ids.sort();
+        new_ids.sort();
+
+        if current_ids != new_ids {
+            return Err(Error::InvalidInput("the new order must contain every image in the post exactly once"));
+        }
 
-    for (_score, doc_address) in top_docs {
-        let retrieved_doc = searcher.doc(doc_address)?;
-        // TODO: every time we load a post here we're loading the whole image into
-        // memory as well. ew.
-        let post = Post::load_id(&conn, retrieved_doc.get_first(post_id_field).unwrap().u64_value() as u32)?;
+        for (position, image_id) in image_ids.iter().enumerate() {
+            conn.execute(
+                "UPDATE post_image SET position=?1 WHERE id=?2",
+                params![position as u32, image_id],
+            )?;
+        }
 
-        posts.push(post.into());
+        Ok(())
     }
 
-    Ok(Json(posts))
+    /// Removes the image specified by the given id from its post's set of images.
+    fn remove(conn: &Connection, media: &MediaStore, image_id: u32) -> Result<(), Error> {
+        let image = PostImage::load_id(conn, image_id)?;
+
+        conn.execute("DELETE FROM post_image WHERE id=?1", params![image_id])?;
+        conn.execute(
+            "UPDATE post_image SET position=position-1 WHERE post_id=?1 AND position>?2",
+            params![image.post_id, image.position],
+        )?;
+
+        media.release(conn, &image.hash)
+    }
 }
 
-/// Returns the profile picture for the requested user id
-#[get("/profile-pic/<req_user_id>")]
-fn profile_pic(_user: User, db: State<DbConn>, req_user_id: u32) -> Result<Content<Vec<u8>>, Error> {
-    let conn = db.lock().unwrap();
-    Ok(Content(ContentType::PNG, User::get_profile_pic(&conn, req_user_id)?))
+impl Owned for PostImage {
+    /// Images are owned by the user that made the post they belong to
+    fn owner_id(conn: &Connection, image_id: u32) -> Result<u32, Error> {
+        Ok(conn.query_row(
+            "SELECT post.user_id FROM post_image JOIN post ON post.id = post_image.post_id
+                    WHERE post_image.id=?1",
+            params![image_id],
+            |row| row.get(0)
+        )?)
+    }
 }
 
-/// Returns the image for the requested post id
-// TODO: figure out how to get the browser to cache this stuff properly
-#[get("/post-image/<post_id>")]
-fn post_image(_user: User, db: State<DbConn>, post_id: u32) -> Result<Content<Vec<u8>>, Error> {
-    let conn = db.lock().unwrap();
-    Ok(Content(ContentType::JPEG, Post::get_image(&conn, post_id)?))
+/// Representation of a comment on a post in the database
+#[derive(Debug, PartialEq)]
+struct Comment {
+    id: u32,
+    /// The post this comment was made on
+    post_id: u32,
+    /// The user that made this comment
+    user_id: u32,
+    /// The top-level comment this comment is a reply to, if any
+    ///
+    /// Replies are only ever one level deep.
+    parent_id: Option<u32>,
+    body: String,
+    created_at: NaiveDateTime,
+    edited_at: Option<NaiveDateTime>
 }
 
-/// Creates a post for whatever user makes the request using the provided post
-/// information.
-///
-/// Returns the post's id upon successful creation.
-#[post("/create-post", format = "json", data = "<post_info>")]
-fn create_post(
-    user: User,
-    db: State<DbConn>,
-    index_writer: State<Mutex<IndexWriter>>,
-    schema: State<Schema>,
-    post_info: Json<PostInfo>
-) -> Result<status::Created<Json<PostCreationResponse>>, Error> {
-    let conn = db.lock().unwrap();
-    let mut index_writer = index_writer.lock().unwrap();
-    Post::create_new(&conn, &mut index_writer, &schema, &post_info, user.user_id)?;
+impl Comment {
+    /// Creates a table in the given database for storing this struct.
+    ///
+    /// The table will only be created if it does not already exist.
+    fn create_table(conn: &Connection) -> Result<(), Error> {
+        Ok(conn.execute(
+            "CREATE TABLE if not exists comment (
+                    id                      INTEGER PRIMARY KEY,
+                    post_id                 INTEGER NOT NULL,
+                    user_id                 INTEGER NOT NULL,
+                    parent_id               INTEGER,
+                    body                    TEXT NOT NULL,
+                    created_at              TEXT NOT NULL,
+                    edited_at               TEXT
+                    )",
+            params![],
+        ).map(|_| ())?)
+    }
 
-    Ok(status::Created("".to_string(), Some(Json(PostCreationResponse { post_id: conn.last_insert_rowid() as u32 }))))
-}
+    /// Inserts a new comment on the given post based on the given comment info
+    /// and user id.
+    ///
+    /// Replying to a reply attaches the new comment to the top-level comment
+    /// instead, keeping threads one level deep.
+    ///
+    /// Returns the id of the new comment.
+    fn create_new(conn: &Connection, post_id: u32, cinfo: &CommentInfo, user_id: u32) -> Result<u32, Error> {
+        if cinfo.body.trim().is_empty() {
+            return Err(Error::InvalidInput("comment body cannot be empty"));
+        }
 
-/// Set the image for the post with the given id to the provided image data.
-// TODO: all of these routes should be put into a tree structure instead of
-// being flat
-//
-// for example /api/post/<id>/image/set instead of /api/set-post-image/<id>
-#[post("/set-post-image/<post_id>", format = "binary", data = "<data>")]
-fn set_post_image(_user: User, db: State<DbConn>, data: Data, post_id: u32) -> Result<(), Error> {
-    let conn = db.lock().unwrap();
-    let mut data_buf = vec![];
-    let mut jpeg_image_data = vec![];
+        if !Post::exists(conn, post_id)? {
+            return Err(Error::NotFound);
+        }
 
-    // 10 MB limit
-    // TODO: more precise error handling throughout
-    // TODO: reuse a single buffer rather than writing image data to a separate one.
-    // too lazy to dirty the code doing it right now :D
-    data.open().take(10485760).read_to_end(&mut data_buf).map_err(|_| Error::ImageUploadFailed)?;
-    load_from_memory(&data_buf)
-        .map_err(|_| Error::ImageUploadFailed)?
-        .resize(1000, 1000, image::FilterType::CatmullRom)
-        .write_to(&mut jpeg_image_data, image::ImageOutputFormat::JPEG(90))
-        .map_err(|_| Error::ImageUploadFailed)?;
+        let parent_id = match cinfo.parent_id {
+            Some(parent_id) => {
+                let parent = Comment::load_id(conn, parent_id)?;
+                if parent.post_id != post_id {
+                    return Err(Error::InvalidInput("a reply must be on the same post as its parent"));
+                }
 
-    Post::set_image(&conn, post_id, &jpeg_image_data).map(|_| ())
-}
+                Some(parent.parent_id.unwrap_or(parent.id))
+            },
+            None => None
+        };
 
-/// Route used to create a new user
-// TODO: right now my error type does not implement responder so returning an error
-// here returns a 500 to the client and logs the error to the console
-#[post("/signup", format = "json", data = "<reg_info>")]
-fn signup(
-    mut cookies: Cookies,
-    reg_info: Json<RegisterInfo>,
-    db: State<DbConn>,
-    key: State<ArgonSecretKey>
-) -> Result<status::Created<Json<UserInfo>>, Error> {
-    let conn = db.lock().unwrap();
-    User::create_new(&conn, &reg_info, &key.0)?;
+        conn.execute(
+            "INSERT INTO comment (post_id, user_id, parent_id, body, created_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![post_id, user_id, parent_id, cinfo.body, Utc::now().naive_utc()],
+        )?;
 
-    let user_id = conn.last_insert_rowid() as u32;
-    let user_info = UserInfo {
-        user_id: user_id,
-        // TODO: these clones are unnecessary
-        display_name: reg_info.display_name.clone(),
-        real_name: reg_info.real_name.clone()
-    };
+        Ok(conn.last_insert_rowid() as u32)
+    }
 
-    // TODO: set the secure flag on this cookie when not in dev mode
-    cookies.add_private(Cookie::new("user_id", user_id.to_string()));
-    Ok(status::Created("".to_string(), Some(Json(user_info))))
-}
+    /// Loads the comment specified by the given id from the database.
+    fn load_id(conn: &Connection, comment_id: u32) -> Result<Self, Error> {
+        Ok(conn.query_row(
+            "SELECT id, post_id, user_id, parent_id, body, created_at, edited_at FROM comment WHERE id=?1",
+            params![comment_id],
+            |row| {
+                Ok(Comment {
+                    id: row.get(0)?,
+                    post_id: row.get(1)?,
+                    user_id: row.get(2)?,
+                    parent_id: row.get(3)?,
+                    body: row.get(4)?,
+                    created_at: row.get(5)?,
+                    edited_at: row.get(6)?
+                })
+            }
+        )?)
+    }
 
-// TODO: right now logging in will quickly fail if a user enters an email that doesn't
-// exist, making it trivial to probe for whether or not an account exists on the server
-//
-// should fix that
-#[post("/login", format = "json", data = "<login_info>")]
-fn login(
-    mut cookies: Cookies,
-    login_info: Json<LoginInfo>,
-    db: State<DbConn>,
-    key: State<ArgonSecretKey>
-) -> Result<status::Accepted<Json<UserInfo>>, Error> {
-    let conn = db.lock().unwrap();
-    let user = User::load_email(&conn, &login_info.email)?;
+    /// Loads a page of `num` top-level comments on the given post, oldest first,
+    /// skipping the first `offset` of them.
+    ///
+    /// Each top-level comment comes with all of its replies. `viewer_id` is the
+    /// id of the user the comments are being loaded for.
+    fn load_page(conn: &Connection, post_id: u32, num: u32, offset: u32, viewer_id: u32) -> Result<Vec<CommentDetails>, Error> {
+        let mut stmt = conn.prepare(
+            "SELECT id, post_id, user_id, parent_id, body, created_at, edited_at FROM comment
+                    WHERE post_id=?1 AND parent_id IS NULL ORDER BY id ASC LIMIT ?2 OFFSET ?3"
+        )?;
+        let mut replies_stmt = conn.prepare(
+            "SELECT id, post_id, user_id, parent_id, body, created_at, edited_at FROM comment
+                    WHERE parent_id=?1 ORDER BY id ASC"
+        )?;
 
-    if user.auth(&login_info, &key.0)? {
-        cookies.add_private(Cookie::new("user_id", user.user_id.to_string()));
-        Ok(status::Accepted(Some(Json(user.into()))))
-    } else {
-        Err(Error::LoginFailed)
+        let comment_iter = stmt.query_map(params![post_id, num, offset], CommentDetails::from_row)?;
+        let mut comments = comment_iter.map(|res| match res {
+            Ok(v) => Ok(v),
+            Err(e) => Err(Error::from(e))
+        }).collect::<Result<Vec<_>, Error>>()?;
+
+        for comment in &mut comments {
+            let reply_iter = replies_stmt.query_map(params![comment.id], CommentDetails::from_row)?;
+            comment.replies = reply_iter.map(|res| match res {
+                Ok(v) => Ok(v),
+                Err(e) => Err(Error::from(e))
+            }).collect::<Result<Vec<_>, Error>>()?;
+
+            comment.reactions = ReactionTarget::comment(comment.id).summarize(conn, viewer_id)?;
+            for reply in &mut comment.replies {
+                reply.reactions = ReactionTarget::comment(reply.id).summarize(conn, viewer_id)?;
+            }
+        }
+
+        Ok(comments)
+    }
+
+    /// Replaces the body of the comment specified by the given id, recording
+    /// when the edit happened.
+    fn update(conn: &Connection, comment_id: u32, body: &str) -> Result<(), Error> {
+        if body.trim().is_empty() {
+            return Err(Error::InvalidInput("comment body cannot be empty"));
+        }
+
+        Ok(conn.execute(
+            "UPDATE comment SET body=?1, edited_at=?2 WHERE id=?3",
+            params![body, Utc::now().naive_utc(), comment_id],
+        ).map(|_| ())?)
+    }
+
+    /// Deletes the comment specified by the given id along with any replies to it
+    /// and all of their reactions.
+    fn delete(conn: &Connection, comment_id: u32) -> Result<(), Error> {
+        conn.execute(
+            "DELETE FROM reaction WHERE target_kind='comment'
+                    AND target_id IN (SELECT id FROM comment WHERE id=?1 OR parent_id=?1)",
+            params![comment_id],
+        )?;
+
+        Ok(conn.execute(
+            "DELETE FROM comment WHERE id=?1 OR parent_id=?1",
+            params![comment_id],
+        ).map(|_| ())?)
     }
 }
 
-#[post("/logout")]
-fn logout(mut cookies: Cookies) -> Status {
-    cookies.remove_private(Cookie::named("user_id"));
-    Status::Ok
+impl Owned for Comment {
+    fn owner_id(conn: &Connection, comment_id: u32) -> Result<u32, Error> {
+        Ok(conn.query_row(
+            "SELECT user_id FROM comment WHERE id=?1",
+            params![comment_id],
+            |row| row.get(0)
+        )?)
+    }
 }
 
-#[post("/me")]
-fn me_authed(user: User) -> Json<UserInfo> {
-    Json(user.into())
+/// The kind of thing a reaction can be attached to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReactionTargetKind {
+    Post,
+    Comment
 }
 
-#[post("/me", rank = 2)]
-fn me() -> status::Custom<()> {
-    status::Custom(Status::Unauthorized, ())
+impl ReactionTargetKind {
+    /// The name used for this kind in the database and in routes
+    fn as_str(self) -> &'static str {
+        match self {
+            ReactionTargetKind::Post => "post",
+            ReactionTargetKind::Comment => "comment"
+        }
+    }
 }
 
-/// A "catch-all" to redirect path requests to the index since we are building a SPA
-// TODO: perhaps we should still return a 404 for anything that's not a path?
-#[catch(404)]
-fn not_found() -> NamedFile {
-    NamedFile::open(Path::new(concat!(root_dir!(), "/svelte-app/public/index.html"))).unwrap()
+impl<'a> FromParam<'a> for ReactionTargetKind {
+    type Error = &'a RawStr;
+
+    fn from_param(param: &'a RawStr) -> Result<Self, Self::Error> {
+        match param.as_str() {
+            "post" => Ok(ReactionTargetKind::Post),
+            "comment" => Ok(ReactionTargetKind::Comment),
+            _ => Err(param)
+        }
+    }
 }
 
-/// Performs any necessary database setup upon application start.
+/// A post or comment that users can react to with emoji
 ///
-/// Can be called multiple times without issue.
-fn init_database(conn: &Connection) -> Result<(), Error> {
-    User::create_table(conn)?;
-    Post::create_table(conn)
+/// A user can react to the same target with any number of different emoji, but
+/// only once with each.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ReactionTarget {
+    kind: ReactionTargetKind,
+    id: u32
 }
 
-/// Builds and returns the search schema.
-fn init_search_schema() -> Schema {
-    let mut schema_builder = Schema::builder();
+impl ReactionTarget {
+    fn post(post_id: u32) -> Self {
+        ReactionTarget { kind: ReactionTargetKind::Post, id: post_id }
+    }
 
-    schema_builder.add_text_field("body", TEXT);
-    schema_builder.add_u64_field("post_id", INDEXED | STORED);
-    schema_builder.add_u64_field("user_id", INDEXED);
-    schema_builder.add_date_field("created_at", INDEXED);
+    fn comment(comment_id: u32) -> Self {
+        ReactionTarget { kind: ReactionTargetKind::Comment, id: comment_id }
+    }
 
-    schema_builder.build()
-}
+    /// Creates a table in the given database for storing reactions.
+    ///
+    /// The table will only be created if it does not already exist.
+    fn create_table(conn: &Connection) -> Result<(), Error> {
+        Ok(conn.execute(
+            "CREATE TABLE if not exists reaction (
+                    user_id                 INTEGER NOT NULL,
+                    target_kind             TEXT NOT NULL,
+                    target_id               INTEGER NOT NULL,
+                    emoji                   TEXT NOT NULL,
+                    created_at              TEXT NOT NULL,
+                    PRIMARY KEY (user_id, target_kind, target_id, emoji)
+                    )",
+            params![],
+        ).map(|_| ())?)
+    }
 
-/// Create a Rocket instance managing the given database connection
-fn rocket(conn: Connection, index: Index, schema: Schema) -> Result<rocket::Rocket, Error> {
-    init_database(&conn)?;
+    /// Returns true if the post or comment being reacted to exists
+    fn exists(self, conn: &Connection) -> Result<bool, Error> {
+        match self.kind {
+            ReactionTargetKind::Post => Post::exists(conn, self.id),
+            ReactionTargetKind::Comment => {
+                let mut stmt = conn.prepare("SELECT id FROM comment WHERE id=?1")?;
+                Ok(stmt.exists(params![self.id])?)
+            }
+        }
+    }
 
-    #[cfg(feature = "deployable")]
-    let static_files_dir = concat!(root_dir!(), "static");
-    #[cfg(not(feature = "deployable"))]
-    let static_files_dir = concat!(root_dir!(), "/svelte-app/public");
+    /// Records a reaction with the given emoji from the given user.
+    ///
+    /// Reacting with an emoji the user has already reacted with does nothing.
+    fn add(self, conn: &Connection, user_id: u32, emoji: &str) -> Result<(), Error> {
+        if !is_single_emoji(emoji) {
+            return Err(Error::InvalidInput("a reaction must be a single emoji"));
+        }
 
-    Ok(
-        rocket::ignite()
-            // TODO: use a rwlock here?
-            .manage(Mutex::new(conn))
-            // search index writer with 50 MB heap
-            .manage(Mutex::new(index.writer(50_000_000)?))
-            .manage(index.reader_builder()
-                .reload_policy(ReloadPolicy::OnCommit)
-                .try_into()?
-            )
-            .manage(schema)
-            .attach(AdHoc::on_attach("Argon Secret Key", |rocket| {
-                // TODO: right now this key gets printed by rocket to the console
-                // every time the binary is launched which is kind of annoying
-                let key = rocket.config()
-                    .get_str("argon_secret_key")
-                    .unwrap()
-                    .to_string();
-    
-                Ok(rocket.manage(ArgonSecretKey(key)))
-            }))
-            // TODO: bundle static files into binary for easy deploy?
-            // TODO: make a crate to manage boilerplate for serving static files from
-            // a hashmap generated at compile time?
-            .mount("/", StaticFiles::from(static_files_dir))
-            .mount("/api", routes![
-                signup,
-                login,
-                logout,
-                me,
-                me_authed,
-                profile_pic,
-                user_info,
-                users,
-                create_post,
-                set_post_image,
-                post_image,
-                recent_posts,
-                search_posts
-            ])
-            .register(catchers![not_found])
-    )
-}
+        if !self.exists(conn)? {
+            return Err(Error::NotFound);
+        }
 
-fn main() -> Result<(), Error> {
-    let search_index_dir = Path::new(concat!(root_dir!(), "/search_index"));
-    if !search_index_dir.exists() {
-        create_dir(&search_index_dir)?;
+        Ok(conn.execute(
+            "INSERT OR IGNORE INTO reaction (user_id, target_kind, target_id, emoji, created_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![user_id, self.kind.as_str(), self.id, emoji, Utc::now().naive_utc()],
+        ).map(|_| ())?)
     }
 
-    let index_dir = MmapDirectory::open(&search_index_dir)?;
-    let search_schema = init_search_schema();
+    /// Removes the given user's reaction with the given emoji, if there is one.
+    fn remove(self, conn: &Connection, user_id: u32, emoji: &str) -> Result<(), Error> {
+        Ok(conn.execute(
+            "DELETE FROM reaction WHERE user_id=?1 AND target_kind=?2 AND target_id=?3 AND emoji=?4",
+            params![user_id, self.kind.as_str(), self.id, emoji],
+        ).map(|_| ())?)
+    }
 
-    // TODO: more configurable database persistence?
-    // rocket pretty prints the error when it drops if one occurs
-    let _ = rocket(
-        Connection::open(concat!(root_dir!(), "/db.db3"))?,
-        Index::open_or_create(index_dir, search_schema.clone())?,
-        search_schema
-    )?.launch();
+    /// Returns the number of reactions with each emoji, in the order the emoji
+    /// were first used.
+    ///
+    /// `viewer_id` is the id of the user the summary is being loaded for.
+    fn summarize(self, conn: &Connection, viewer_id: u32) -> Result<Vec<ReactionSummary>, Error> {
+        let mut stmt = conn.prepare(
+            "SELECT emoji, COUNT(*), MAX(user_id=?3) FROM reaction WHERE target_kind=?1 AND target_id=?2
+                    GROUP BY emoji ORDER BY MIN(created_at) ASC"
+        )?;
+        let summary_iter = stmt.query_map(params![self.kind.as_str(), self.id, viewer_id], |row| {
+            Ok(ReactionSummary {
+                emoji: row.get(0)?,
+                count: row.get(1)?,
+                reacted: row.get(2)?
+            })
+        })?;
 
-    Ok(())
+        summary_iter.map(|res| match res {
+            Ok(v) => Ok(v),
+            Err(e) => Err(Error::from(e))
+        }).collect()
+    }
+
+    /// Returns every reaction to this target along with the user that made it,
+    /// oldest first.
+    fn load_all(self, conn: &Connection) -> Result<Vec<ReactionUserInfo>, Error> {
+        let mut stmt = conn.prepare(
+            "SELECT reaction.emoji, user.user_id, user.display_name, user.real_name FROM reaction
+                    JOIN user ON user.user_id = reaction.user_id
+                    WHERE reaction.target_kind=?1 AND reaction.target_id=?2
+                    ORDER BY reaction.created_at ASC"
+        )?;
+        let reaction_iter = stmt.query_map(params![self.kind.as_str(), self.id], |row| {
+            Ok(ReactionUserInfo {
+                emoji: row.get(0)?,
+                user: UserInfo {
+                    user_id: row.get(1)?,
+                    display_name: row.get(2)?,
+                    real_name: row.get(3)?
+                }
+            })
+        })?;
+
+        reaction_iter.map(|res| match res {
+            Ok(v) => Ok(v),
+            Err(e) => Err(Error::from(e))
+        }).collect()
+    }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use rocket::Response;
-    use rocket::local::Client;
-    use std::fs::File;
+/// Representation of an invite code in the database
+struct Invite;
 
-    fn user_id_cookie(response: &Response) -> Option<Cookie<'static>> {
-        let cookie = response.headers()
-            .get("Set-Cookie")
-            .filter(|v| v.starts_with("user_id"))
-            .nth(0)
-            .and_then(|val| Cookie::parse_encoded(val).ok());
-    
-        cookie.map(|c| c.into_owned())
+impl Invite {
+    /// Creates a table in the given database for storing invites.
+    ///
+    /// The table will only be created if it does not already exist.
+    fn create_table(conn: &Connection) -> Result<(), Error> {
+        Ok(conn.execute(
+            "CREATE TABLE if not exists invite (
+                    code                    TEXT PRIMARY KEY,
+                    created_by              INTEGER NOT NULL,
+                    created_at              TEXT NOT NULL,
+                    expires_at              TEXT,
+                    max_uses                INTEGER,
+                    uses                    INTEGER NOT NULL,
+                    revoked                 INTEGER NOT NULL
+                    )",
+            params![],
+        ).map(|_| ())?)
     }
 
-    fn login(client: &Client, email: String, password: String) -> Option<Cookie<'static>> {
-        let login_info = LoginInfo {
-            email,
-            password
-        };
+    /// Creates a new invite on behalf of the given user and returns its code.
+    fn create_new(conn: &Connection, created_by: u32, info: &InviteCreationInfo) -> Result<String, Error> {
+        if info.max_uses == Some(0) {
+            return Err(Error::InvalidInput("an invite must be usable at least once"));
+        }
 
-        let response = client.post("/api/login")
-            .header(ContentType::JSON)
-            .body(serde_json::to_string(&login_info).unwrap())
-            .dispatch();
+        let code = generate_token(16);
+        let created_at = Utc::now().naive_utc();
+        let expires_at = info.expires_in_hours.map(|hours| created_at + chrono::Duration::hours(hours as i64));
+
+        conn.execute(
+            "INSERT INTO invite (code, created_by, created_at, expires_at, max_uses, uses, revoked)
+                    VALUES (?1, ?2, ?3, ?4, ?5, 0, 0)",
+            params![code, created_by, created_at, expires_at, info.max_uses],
+        )?;
 
-        user_id_cookie(&response)
+        Ok(code)
     }
 
-    fn create_dummy_user(conn: &Connection, email: String, password: String, key: String) -> Result<(), Error> {
-        let rinfo = RegisterInfo {
-            email,
-            password,
-            display_name: "dummy".to_string(),
-            real_name: "Some Dummy".to_string()
-        };
+    /// Loads and returns all invites, newest first
+    fn load_all(conn: &Connection) -> Result<Vec<InviteDetails>, Error> {
+        let mut stmt = conn.prepare(
+            "SELECT code, created_by, created_at, expires_at, max_uses, uses, revoked FROM invite
+                ORDER BY created_at DESC"
+        )?;
+        let invite_iter = stmt.query_map(params![], |row| {
+            Ok(InviteDetails {
+                code: row.get(0)?,
+                created_by: row.get(1)?,
+                created_at: row.get::<_, NaiveDateTime>(2)?.timestamp(),
+                expires_at: row.get::<_, Option<NaiveDateTime>>(3)?.map(|t| t.timestamp()),
+                max_uses: row.get(4)?,
+                uses: row.get(5)?,
+                revoked: row.get(6)?
+            })
+        })?;
 
-        User::create_new(&conn, &rinfo, &key)
+        invite_iter.map(|res| match res {
+            Ok(v) => Ok(v),
+            Err(e) => Err(Error::from(e))
+        }).collect()
     }
 
-    #[test]
-    fn test_user_database() -> Result<(), Error> {
-        let conn = Connection::open_in_memory()?;
-        init_database(&conn)?;
+    /// Stops the invite with the given code from being used again
+    fn revoke(conn: &Connection, code: &str) -> Result<(), Error> {
+        match conn.execute("UPDATE invite SET revoked=1 WHERE code=?1", params![code])? {
+            0 => Err(Error::NotFound),
+            _ => Ok(())
+        }
+    }
 
-        let email = String::from("some_email@gmail.com");
-        let display_name = String::from("display_name");
-        let real_name = String::from("real_name");
+    /// Uses up one use of the invite with the given code.
+    ///
+    /// Errors if the invite doesn't exist or can't be used anymore.
+    fn redeem(conn: &Connection, code: &str) -> Result<(), Error> {
+        let redeemed = conn.execute(
+            "UPDATE invite SET uses=uses+1
+                WHERE code=?1 AND revoked=0
+                AND (expires_at IS NULL OR expires_at > ?2)
+                AND (max_uses IS NULL OR uses < max_uses)",
+            params![code, Utc::now().naive_utc()],
+        )?;
 
-        let identicon = Identicon::new_default(&(display_name.clone() + &email + &real_name));
-        let data = identicon.export_file_data(ImageType::PNG);
+        match redeemed {
+            0 => Err(Error::InvalidInput("invite code is invalid or has expired")),
+            _ => Ok(())
+        }
+    }
+}
+
+/// Web client posts this to create a new user
+#[derive(Serialize, Deserialize)]
+struct RegisterInfo {
+    email: String,
+    password: String,
+    display_name: String,
+    real_name: String,
+    /// Required when the server is invite-only, apart from for the first user
+    #[serde(default)]
+    invite_code: Option<String>
+}
+
+/// Admins post this to create a new invite
+#[derive(Serialize, Deserialize)]
+struct InviteCreationInfo {
+    /// How long the invite can be used for, forever if not given
+    expires_in_hours: Option<u32>,
+    /// How many people can sign up with the invite, unlimited if not given
+    max_uses: Option<u32>
+}
+
+/// Admins receive this when listing invites
+#[derive(Serialize, Deserialize)]
+struct InviteDetails {
+    code: String,
+    /// The user that created the invite
+    created_by: u32,
+    created_at: i64,
+    expires_at: Option<i64>,
+    max_uses: Option<u32>,
+    uses: u32,
+    revoked: bool
+}
+
+/// A token emailed to a user that lets them choose a new password
+struct PasswordReset;
+
+impl PasswordReset {
+    /// Creates a table in the given database for storing password reset tokens.
+    ///
+    /// The table will only be created if it does not already exist.
+    fn create_table(conn: &Connection) -> Result<(), Error> {
+        Ok(conn.execute(
+            "CREATE TABLE if not exists password_reset (
+                    token_hash              TEXT PRIMARY KEY,
+                    user_id                 INTEGER NOT NULL,
+                    expires_at              TEXT NOT NULL
+                    )",
+            params![],
+        ).map(|_| ())?)
+    }
+
+    /// Creates a new reset token for the given user and returns it.
+    ///
+    /// Only a hash of the token is stored.
+    fn create_new(conn: &Connection, user_id: u32) -> Result<String, Error> {
+        let token = generate_token(32);
+        let expires_at = Utc::now().naive_utc() + chrono::Duration::minutes(PASSWORD_RESET_EXPIRY_MINUTES);
 
-        let me = User {
-            user_id: 1,
-            hash: "kalnfdanf".to_string(),
-            email,
-            created_at: Utc::now().naive_utc(),
-            display_name,
-            real_name,
-            profile_pic: data
-        };
         conn.execute(
-            "INSERT INTO user (user_id, hash, email, created_at, display_name, real_name, profile_pic)
-                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            params![me.user_id, me.hash, me.email, me.created_at, me.display_name, me.real_name, me.profile_pic],
+            "INSERT INTO password_reset (token_hash, user_id, expires_at) VALUES (?1, ?2, ?3)",
+            params![session::hash_token(&token), user_id, expires_at],
         )?;
 
-        let user = User::load_id(&conn, 1)?;
-        assert_eq!(&user, &me);
+        Ok(token)
+    }
+
+    /// Uses up the given reset token and returns the id of the user it was
+    /// created for.
+    ///
+    /// All of that user's other reset tokens stop working too. Errors if the
+    /// token doesn't exist or has expired.
+    fn redeem(conn: &Connection, token: &str) -> Result<u32, Error> {
+        let user_id: Option<u32> = conn.query_row(
+            "SELECT user_id FROM password_reset WHERE token_hash=?1 AND expires_at > ?2",
+            params![session::hash_token(token), Utc::now().naive_utc()],
+            |row| row.get(0)
+        ).optional()?;
+        let user_id = user_id.ok_or(Error::InvalidInput("reset token is invalid or has expired"))?;
+
+        conn.execute("DELETE FROM password_reset WHERE user_id=?1", params![user_id])?;
+        Ok(user_id)
+    }
+}
+
+/// Admins receive this after creating an invite
+#[derive(Serialize, Deserialize)]
+struct InviteCreationResponse {
+    code: String
+}
+
+/// Web client posts this to change the user's password
+#[derive(Serialize, Deserialize)]
+struct PasswordChangeInfo {
+    old_password: String,
+    new_password: String
+}
+
+/// Web client posts this to have a password reset link emailed to a user
+#[derive(Serialize, Deserialize)]
+struct PasswordResetRequest {
+    email: String
+}
+
+/// Web client posts this to choose a new password using a reset token
+#[derive(Serialize, Deserialize)]
+struct PasswordResetInfo {
+    token: String,
+    new_password: String
+}
+
+/// Web client posts this to change the user's profile
+///
+/// Fields that are left out are left unchanged.
+#[derive(Serialize, Deserialize)]
+struct ProfileUpdateInfo {
+    display_name: Option<String>,
+    real_name: Option<String>,
+    /// Replaces all of the user's profile details when given
+    details: Option<ProfileDetails>
+}
+
+/// Web client posts this to create a new post
+#[derive(Serialize, Deserialize)]
+struct PostInfo {
+    body: String
+}
+
+/// The columns that `PostDetails::from_row` expects to be selected from the
+/// `post` table
+const POST_DETAILS_COLUMNS: &str = "id, body, created_at, edited_at, user_id,
+    (SELECT COUNT(*) FROM comment WHERE comment.post_id = post.id), taken_at";
+
+/// Web client receives this to display posts
+#[derive(Serialize, Deserialize)]
+struct PostDetails {
+    id: u32,
+    body: String,
+    created_at: i64,
+    /// When the body of this post was last changed, if ever
+    edited_at: Option<i64>,
+    user_id: u32,
+    /// When the photos in this post were taken, if the user chose to share it
+    ///
+    /// This is in the camera's local time rather than UTC.
+    taken_at: Option<i64>,
+    /// The ids of this post's images in order
+    image_ids: Vec<u32>,
+    /// The number of comments (including replies) made on this post
+    comment_count: u32,
+    reactions: Vec<ReactionSummary>
+}
+
+impl PostDetails {
+    /// Builds post details from a row containing `POST_DETAILS_COLUMNS`
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(PostDetails {
+            id: row.get(0)?,
+            body: row.get(1)?,
+            created_at: row.get::<_, NaiveDateTime>(2)?.timestamp(),
+            edited_at: row.get::<_, Option<NaiveDateTime>>(3)?.map(|t| t.timestamp()),
+            user_id: row.get(4)?,
+            taken_at: row.get::<_, Option<NaiveDateTime>>(6)?.map(|t| t.timestamp()),
+            image_ids: vec![],
+            comment_count: row.get(5)?,
+            reactions: vec![]
+        })
+    }
+
+    /// Loads the parts of the details that are stored outside of the `post` table
+    ///
+    /// `viewer_id` is the id of the user the details are being loaded for.
+    fn load_attachments(&mut self, conn: &Connection, viewer_id: u32) -> Result<(), Error> {
+        self.image_ids = PostImage::ids_for_post(conn, self.id)?;
+        self.reactions = ReactionTarget::post(self.id).summarize(conn, viewer_id)?;
+
+        Ok(())
+    }
+}
+
+/// Web client receives this after creating a post
+#[derive(Serialize, Deserialize)]
+struct PostCreationResponse {
+    post_id: u32
+}
+
+/// Web client receives this after adding an image to a post
+#[derive(Serialize, Deserialize)]
+struct PostImageCreationResponse {
+    image_id: u32
+}
+
+/// Web client posts this to rearrange the images in a post
+#[derive(Serialize, Deserialize)]
+struct PostImageOrder {
+    /// The ids of every image in the post in their new order
+    image_ids: Vec<u32>
+}
+
+/// Web client posts this to comment on a post
+#[derive(Serialize, Deserialize)]
+struct CommentInfo {
+    body: String,
+    /// The comment being replied to, if any
+    parent_id: Option<u32>
+}
+
+/// Web client posts this to edit a comment
+#[derive(Serialize, Deserialize)]
+struct CommentEditInfo {
+    body: String
+}
+
+/// Web client receives this to display comments
+#[derive(Serialize, Deserialize)]
+struct CommentDetails {
+    id: u32,
+    post_id: u32,
+    user_id: u32,
+    parent_id: Option<u32>,
+    body: String,
+    created_at: i64,
+    edited_at: Option<i64>,
+    /// Replies to this comment, oldest first
+    ///
+    /// Always empty for replies themselves.
+    replies: Vec<CommentDetails>,
+    reactions: Vec<ReactionSummary>
+}
+
+impl CommentDetails {
+    /// Builds comment details (without any replies) from a row containing the
+    /// columns of the `comment` table in order
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(CommentDetails {
+            id: row.get(0)?,
+            post_id: row.get(1)?,
+            user_id: row.get(2)?,
+            parent_id: row.get(3)?,
+            body: row.get(4)?,
+            created_at: row.get::<_, NaiveDateTime>(5)?.timestamp(),
+            edited_at: row.get::<_, Option<NaiveDateTime>>(6)?.map(|t| t.timestamp()),
+            replies: vec![],
+            reactions: vec![]
+        })
+    }
+}
+
+/// Web client receives this after creating a comment
+#[derive(Serialize, Deserialize)]
+struct CommentCreationResponse {
+    comment_id: u32
+}
+
+/// Web client posts this to add or remove a reaction
+#[derive(Serialize, Deserialize)]
+struct ReactionInfo {
+    emoji: String
+}
+
+/// The reactions made with a single emoji on a post or comment
+#[derive(Serialize, Deserialize)]
+struct ReactionSummary {
+    emoji: String,
+    /// The number of users that reacted with this emoji
+    count: u32,
+    /// Whether or not the user viewing the post reacted with this emoji
+    reacted: bool
+}
+
+/// A single reaction and the user that made it
+#[derive(Serialize, Deserialize)]
+struct ReactionUserInfo {
+    emoji: String,
+    user: UserInfo
+}
+
+/// Web client receives this when a request fails
+#[derive(Serialize, Deserialize)]
+struct ErrorResponse {
+    /// The reason phrase of the response's HTTP status
+    error: String,
+    /// A description of what went wrong, if there is one
+    message: Option<String>
+}
+
+/// Web client posts this to login.
+#[derive(Serialize, Deserialize)]
+struct LoginInfo {
+    email: String,
+    password: String
+}
+
+/// Information about the requested user
+// TODO: reorganize this whole file so intent is clearer
+#[derive(Serialize, Deserialize)]
+struct UserInfo {
+    user_id: u32,
+    display_name: String,
+    real_name: String
+}
+
+impl From<User> for UserInfo {
+    fn from(user: User) -> Self {
+        Self {
+            user_id: user.user_id,
+            display_name: user.display_name,
+            real_name: user.real_name
+        }
+    }
+}
+
+/// Everything on a user's profile that the requesting user can see
+#[derive(Serialize, Deserialize)]
+struct UserProfile {
+    #[serde(flatten)]
+    info: UserInfo,
+    #[serde(flatten)]
+    details: ProfileDetails
+}
+
+/// Returns the profile of the requested user id
+#[get("/user-info/<req_user_id>")]
+fn user_info(user: User, db: State<DbConn>, req_user_id: u32) -> Result<Json<UserProfile>, Error> {
+    let conn = db.lock().unwrap();
+    let req_user = User::load_id(&conn, req_user_id)?;
+
+    Ok(Json(UserProfile {
+        info: req_user.into(),
+        details: ProfileDetails::load(&conn, req_user_id, user.user_id)?
+    }))
+}
+
+/// Returns information about all registered users
+#[get("/users")]
+fn users(_user: User, db: State<DbConn>) -> Result<Json<Vec<UserInfo>>, Error> {
+    let conn = db.lock().unwrap();
+    let users = User::load_all(&conn)?;
+
+    Ok(Json(users.into_iter().map(|u| u.into()).collect()))
+}
+
+/// Returns details about recent n recent posts for the given user id
+// TODO: use query parameters more, they're more idiomatic
+// also show up nicer in the network panel
+#[get("/recent-posts?<req_user_id>&<n>")]
+fn recent_posts(user: User, db: State<DbConn>, req_user_id: Option<u32>, n: u32) -> Result<Json<Vec<PostDetails>>, Error> {
+    let conn = db.lock().unwrap();
+    let posts = Post::load_recents_info(&conn, n, req_user_id, user.user_id)?;
+
+    Ok(Json(posts))
+}
+
+/// Searches post bodies using the given query and returns the top 10 results
+#[get("/search-posts?<query_string>")]
+fn search_posts(
+    user: User,
+    db: State<DbConn>,
+    reader: State<IndexReader>,
+    schema: State<Schema>,
+    query_string: String
+) -> Result<Json<Vec<PostDetails>>, Error> {
+    let conn = db.lock().unwrap();
+    let searcher = reader.searcher();
+    let body_field = schema.get_field("body").unwrap();
+    let post_id_field = schema.get_field("post_id").unwrap();
+    let query_parser = QueryParser::for_index(searcher.index(), vec![body_field]);
+    let mut posts = vec![];
+
+    let query = query_parser.parse_query(&query_string)?;
+    let top_docs = searcher.search(&query, &TopDocs::with_limit(10))?;
+
+    for (_score, doc_address) in top_docs {
+        let retrieved_doc = searcher.doc(doc_address)?;
+        let post = Post::load_info(&conn, retrieved_doc.get_first(post_id_field).unwrap().u64_value() as u32, user.user_id)?;
+
+        posts.push(post);
+    }
+
+    Ok(Json(posts))
+}
+
+/// Returns the profile picture for the requested user id at the requested size,
+/// defaulting to full size
+///
+/// Profile pictures can be changed, so browsers always check for a new one.
+#[get("/profile-pic/<req_user_id>?<size>")]
+fn profile_pic(
+    _user: User,
+    db: State<DbConn>,
+    media: State<MediaStore>,
+    req_user_id: u32,
+    size: Option<RenditionSize>
+) -> Result<CachedFile, Error> {
+    let (content_type, hash) = {
+        let conn = db.lock().unwrap();
+        let original_hash = User::profile_pic_hash(&conn, req_user_id)?;
+        let hash = media.rendition_hash(&conn, &original_hash, size.unwrap_or(RenditionSize::Full))?;
+        (media.content_type(&conn, &hash)?, hash)
+    };
+
+    Ok(media.open_cached(content_type, hash)?.always_revalidate())
+}
+
+/// Returns the requested post image at the requested size, defaulting to full
+/// size
+#[get("/post-image/<image_id>?<size>")]
+fn post_image(
+    _user: User,
+    db: State<DbConn>,
+    media: State<MediaStore>,
+    image_id: u32,
+    size: Option<RenditionSize>
+) -> Result<CachedFile, Error> {
+    let (content_type, hash) = {
+        let conn = db.lock().unwrap();
+        let original_hash = PostImage::load_id(&conn, image_id)?.hash;
+        let hash = media.rendition_hash(&conn, &original_hash, size.unwrap_or(RenditionSize::Full))?;
+        (media.content_type(&conn, &hash)?, hash)
+    };
+
+    media.open_cached(content_type, hash)
+}
+
+/// Creates a post for whatever user makes the request using the provided post
+/// information.
+///
+/// Returns the post's id upon successful creation.
+#[post("/create-post", format = "json", data = "<post_info>")]
+fn create_post(
+    user: User,
+    db: State<DbConn>,
+    index_writer: State<Mutex<IndexWriter>>,
+    schema: State<Schema>,
+    post_info: Json<PostInfo>
+) -> Result<status::Created<Json<PostCreationResponse>>, Error> {
+    let conn = db.lock().unwrap();
+    let mut index_writer = index_writer.lock().unwrap();
+    Post::create_new(&conn, &mut index_writer, &schema, &post_info, user.user_id)?;
+
+    Ok(status::Created("".to_string(), Some(Json(PostCreationResponse { post_id: conn.last_insert_rowid() as u32 }))))
+}
+
+/// Replaces the body of the post with the given id, keeping the search index
+/// in sync.
+#[post("/edit-post/<post_id>", format = "json", data = "<post_info>")]
+fn edit_post(
+    user: User,
+    db: State<DbConn>,
+    index_writer: State<Mutex<IndexWriter>>,
+    schema: State<Schema>,
+    post_id: u32,
+    post_info: Json<PostInfo>
+) -> Result<(), Error> {
+    let mut conn = db.lock().unwrap();
+    user.authorize::<Post>(&conn, post_id)?;
+
+    let mut index_writer = index_writer.lock().unwrap();
+    Post::update(&mut conn, &mut index_writer, &schema, post_id, &post_info)
+}
+
+/// Deletes the post with the given id along with everything attached to it,
+/// removing it from the search index.
+#[post("/delete-post/<post_id>")]
+fn delete_post(
+    user: User,
+    db: State<DbConn>,
+    index_writer: State<Mutex<IndexWriter>>,
+    schema: State<Schema>,
+    media: State<MediaStore>,
+    post_id: u32
+) -> Result<(), Error> {
+    let mut conn = db.lock().unwrap();
+    user.authorize::<Post>(&conn, post_id)?;
+
+    let mut index_writer = index_writer.lock().unwrap();
+    Post::delete(&mut conn, &mut index_writer, &schema, &media, post_id)
+}
+
+/// Adds the provided image data to the end of the images for the post with the
+/// given id.
+///
+/// Location and device metadata is always stripped from the image. If
+/// `keep_taken_at` is set, the time the photo was taken is kept as the post's
+/// "taken at" time.
+///
+/// Returns the new image's id.
+// TODO: all of these routes should be put into a tree structure instead of
+// being flat
+//
+// for example /api/post/<id>/image/add instead of /api/add-post-image/<id>
+#[post("/add-post-image/<post_id>?<keep_taken_at>", format = "binary", data = "<data>")]
+fn add_post_image(
+    user: User,
+    db: State<DbConn>,
+    media: State<MediaStore>,
+    data: Data,
+    post_id: u32,
+    keep_taken_at: Option<bool>
+) -> Result<status::Created<Json<PostImageCreationResponse>>, Error> {
+    let image_id = store_post_image(&user, &db, &media, data, post_id, keep_taken_at)?;
+    Ok(status::Created("".to_string(), Some(Json(PostImageCreationResponse { image_id }))))
+}
+
+/// The route `add_post_image` used to be, kept so that older clients can still
+/// upload images
+#[post("/set-post-image/<post_id>", format = "binary", data = "<data>")]
+fn set_post_image(user: User, db: State<DbConn>, media: State<MediaStore>, data: Data, post_id: u32) -> Result<(), Error> {
+    store_post_image(&user, &db, &media, data, post_id, None).map(|_| ())
+}
+
+/// Adds the uploaded image to the end of the given post's images and returns
+/// its id
+fn store_post_image(
+    user: &User,
+    db: &DbConn,
+    media: &MediaStore,
+    data: Data,
+    post_id: u32,
+    keep_taken_at: Option<bool>
+) -> Result<u32, Error> {
+    user.authorize::<Post>(&db.lock().unwrap(), post_id)?;
+
+    let mut data_buf = vec![];
+
+    // 10 MB limit
+    // TODO: more precise error handling throughout
+    data.open().take(10_485_760).read_to_end(&mut data_buf).map_err(|_| Error::ImageUploadFailed)?;
+    // the database isn't locked while resizing so other requests aren't held up
+    let image = ProcessedImage::new(data_buf)?;
+
+    let conn = db.lock().unwrap();
+    let image_id = PostImage::add(&conn, media, post_id, &image)?;
+
+    if let (Some(true), Some(taken_at)) = (keep_taken_at, image.taken_at()) {
+        Post::set_taken_at(&conn, post_id, taken_at)?;
+    }
+
+    Ok(image_id)
+}
+
+/// Rearranges the images of the post with the given id
+#[post("/reorder-post-images/<post_id>", format = "json", data = "<order>")]
+fn reorder_post_images(user: User, db: State<DbConn>, post_id: u32, order: Json<PostImageOrder>) -> Result<(), Error> {
+    let conn = db.lock().unwrap();
+    user.authorize::<Post>(&conn, post_id)?;
+    PostImage::reorder(&conn, post_id, &order.image_ids)
+}
+
+/// Removes the image with the given id from its post
+#[post("/remove-post-image/<image_id>")]
+fn remove_post_image(user: User, db: State<DbConn>, media: State<MediaStore>, image_id: u32) -> Result<(), Error> {
+    let conn = db.lock().unwrap();
+    user.authorize::<PostImage>(&conn, image_id)?;
+    PostImage::remove(&conn, &media, image_id)
+}
+
+/// Creates a comment on the post with the given id for whatever user makes the
+/// request.
+///
+/// Returns the comment's id upon successful creation.
+#[post("/create-comment/<post_id>", format = "json", data = "<comment_info>")]
+fn create_comment(
+    user: User,
+    db: State<DbConn>,
+    post_id: u32,
+    comment_info: Json<CommentInfo>
+) -> Result<status::Created<Json<CommentCreationResponse>>, Error> {
+    let conn = db.lock().unwrap();
+    let comment_id = Comment::create_new(&conn, post_id, &comment_info, user.user_id)?;
+
+    Ok(status::Created("".to_string(), Some(Json(CommentCreationResponse { comment_id }))))
+}
+
+/// Returns n top-level comments (and their replies) on the given post, skipping
+/// the first `offset` of them
+#[get("/post-comments/<post_id>?<n>&<offset>")]
+fn post_comments(
+    user: User,
+    db: State<DbConn>,
+    post_id: u32,
+    n: u32,
+    offset: Option<u32>
+) -> Result<Json<Vec<CommentDetails>>, Error> {
+    let conn = db.lock().unwrap();
+    let comments = Comment::load_page(&conn, post_id, n, offset.unwrap_or(0), user.user_id)?;
+
+    Ok(Json(comments))
+}
+
+/// Replaces the body of the comment with the given id
+#[post("/edit-comment/<comment_id>", format = "json", data = "<comment_info>")]
+fn edit_comment(
+    user: User,
+    db: State<DbConn>,
+    comment_id: u32,
+    comment_info: Json<CommentEditInfo>
+) -> Result<(), Error> {
+    let conn = db.lock().unwrap();
+    user.authorize::<Comment>(&conn, comment_id)?;
+    Comment::update(&conn, comment_id, &comment_info.body)
+}
+
+/// Deletes the comment with the given id along with any replies to it
+#[post("/delete-comment/<comment_id>")]
+fn delete_comment(user: User, db: State<DbConn>, comment_id: u32) -> Result<(), Error> {
+    let conn = db.lock().unwrap();
+    user.authorize::<Comment>(&conn, comment_id)?;
+    Comment::delete(&conn, comment_id)
+}
+
+/// Reacts to the given post or comment with an emoji
+///
+/// `target_kind` is either "post" or "comment".
+#[post("/add-reaction/<target_kind>/<target_id>", format = "json", data = "<reaction_info>")]
+fn add_reaction(
+    user: User,
+    db: State<DbConn>,
+    target_kind: ReactionTargetKind,
+    target_id: u32,
+    reaction_info: Json<ReactionInfo>
+) -> Result<(), Error> {
+    let conn = db.lock().unwrap();
+    ReactionTarget { kind: target_kind, id: target_id }.add(&conn, user.user_id, &reaction_info.emoji)
+}
+
+/// Removes a reaction made by whatever user makes the request from the given
+/// post or comment
+#[post("/remove-reaction/<target_kind>/<target_id>", format = "json", data = "<reaction_info>")]
+fn remove_reaction(
+    user: User,
+    db: State<DbConn>,
+    target_kind: ReactionTargetKind,
+    target_id: u32,
+    reaction_info: Json<ReactionInfo>
+) -> Result<(), Error> {
+    let conn = db.lock().unwrap();
+    ReactionTarget { kind: target_kind, id: target_id }.remove(&conn, user.user_id, &reaction_info.emoji)
+}
+
+/// Returns every reaction made to the given post or comment along with who
+/// made it
+#[get("/reactions/<target_kind>/<target_id>")]
+fn reactions(
+    _user: User,
+    db: State<DbConn>,
+    target_kind: ReactionTargetKind,
+    target_id: u32
+) -> Result<Json<Vec<ReactionUserInfo>>, Error> {
+    let conn = db.lock().unwrap();
+    Ok(Json(ReactionTarget { kind: target_kind, id: target_id }.load_all(&conn)?))
+}
+
+/// Route used to create a new user
+#[post("/signup", format = "json", data = "<reg_info>")]
+#[allow(clippy::too_many_arguments)]
+fn signup(
+    mut cookies: Cookies,
+    reg_info: Json<RegisterInfo>,
+    db: State<DbConn>,
+    media: State<MediaStore>,
+    key: State<ArgonSecretKey>,
+    invite_only: State<InviteOnly>,
+    session_config: State<SessionConfig>,
+    client: ClientInfo
+) -> Result<status::Created<Json<UserInfo>>, Error> {
+    let mut conn = db.lock().unwrap();
+    let tx = conn.transaction()?;
+
+    // the first user has nobody to invite them
+    let user_count: u32 = tx.query_row("SELECT COUNT(*) FROM user", params![], |row| row.get(0))?;
+    if invite_only.0 && user_count > 0 {
+        let code = reg_info.invite_code.as_ref().ok_or(Error::InvalidInput("an invite code is required"))?;
+        Invite::redeem(&tx, code)?;
+    }
+
+    User::create_new(&tx, &media, &reg_info, &key.0)?;
+    let user_id = tx.last_insert_rowid() as u32;
+    tx.commit()?;
+
+    let user_info = UserInfo {
+        user_id,
+        // TODO: these clones are unnecessary
+        display_name: reg_info.display_name.clone(),
+        real_name: reg_info.real_name.clone()
+    };
+
+    Session::start(&mut cookies, &conn, &session_config, user_id, &client)?;
+    Ok(status::Created("".to_string(), Some(Json(user_info))))
+}
+
+/// Logs in the user with the given email and password.
+///
+/// A password is checked even when nobody has the given email, and the same
+/// error is returned either way, so that this can't be used to find out who
+/// has an account.
+#[post("/login", format = "json", data = "<login_info>")]
+fn login(
+    mut cookies: Cookies,
+    login_info: Json<LoginInfo>,
+    db: State<DbConn>,
+    key: State<ArgonSecretKey>,
+    dummy_hash: State<DummyHash>,
+    session_config: State<SessionConfig>,
+    client: ClientInfo
+) -> Result<status::Accepted<Json<UserInfo>>, Error> {
+    let conn = db.lock().unwrap();
+    let user = match User::load_email(&conn, &login_info.email) {
+        Ok(user) => user,
+        Err(Error::DatabaseErr(rusqlite::Error::QueryReturnedNoRows)) => {
+            verify_password(&dummy_hash.0, &login_info.password, &key.0)?;
+            return Err(Error::LoginFailed);
+        },
+        Err(e) => return Err(e)
+    };
+
+    if user.auth(&login_info, &key.0)? {
+        Session::start(&mut cookies, &conn, &session_config, user.user_id, &client)?;
+        Ok(status::Accepted(Some(Json(user.into()))))
+    } else {
+        Err(Error::LoginFailed)
+    }
+}
+
+/// Ends the session making the request
+#[post("/logout")]
+fn logout(mut cookies: Cookies, db: State<DbConn>, session: Option<Session>) -> Result<(), Error> {
+    if let Some(session) = session {
+        Session::revoke(&db.lock().unwrap(), session.user_id, session.id)?;
+    }
+
+    cookies.remove_private(Cookie::named(SESSION_COOKIE));
+    Ok(())
+}
+
+/// Changes the password of whatever user makes the request.
+///
+/// Every other session belonging to the user is ended.
+#[post("/change-password", format = "json", data = "<change_info>")]
+fn change_password(
+    mut user: User,
+    session: Session,
+    change_info: Json<PasswordChangeInfo>,
+    db: State<DbConn>,
+    key: State<ArgonSecretKey>
+) -> Result<(), Error> {
+    let conn = db.lock().unwrap();
+
+    if !user.verify_password(&change_info.old_password, &key.0)? {
+        return Err(Error::LoginFailed);
+    }
+
+    user.set_password(&conn, &change_info.new_password, &key.0)?;
+    Session::revoke_others(&conn, user.user_id, session.id)
+}
+
+/// Emails a password reset link to the user with the given email.
+///
+/// Succeeds whether or not the user exists so that this can't be used to find
+/// out who has an account.
+#[post("/request-password-reset", format = "json", data = "<reset_request>")]
+fn request_password_reset(
+    reset_request: Json<PasswordResetRequest>,
+    db: State<DbConn>,
+    mailer: State<Box<dyn Mailer>>,
+    public_url: State<PublicUrl>
+) -> Result<(), Error> {
+    let token = {
+        let conn = db.lock().unwrap();
+        match User::load_email(&conn, &reset_request.email) {
+            Ok(user) => PasswordReset::create_new(&conn, user.user_id)?,
+            Err(Error::DatabaseErr(rusqlite::Error::QueryReturnedNoRows)) => return Ok(()),
+            Err(e) => return Err(e)
+        }
+    };
+
+    let body = format!(
+        "Someone asked to reset the password for your account. If it was you, \
+        choose a new password here within the next {} minutes:\n\n{}/reset-password?token={}\n\n\
+        If it wasn't you, you can ignore this email.",
+        PASSWORD_RESET_EXPIRY_MINUTES,
+        public_url.0,
+        token
+    );
+
+    // the mail server is only contacted once the database is unlocked
+    if let Err(e) = mailer.send(&reset_request.email, "Reset your password", &body) {
+        error!("Error while sending password reset email: {:?}", e);
+    }
+
+    Ok(())
+}
+
+/// Sets a new password for the user the given reset token was created for.
+///
+/// Every session belonging to the user is ended.
+#[post("/reset-password", format = "json", data = "<reset_info>")]
+fn reset_password(
+    reset_info: Json<PasswordResetInfo>,
+    db: State<DbConn>,
+    key: State<ArgonSecretKey>
+) -> Result<(), Error> {
+    let mut conn = db.lock().unwrap();
+    let tx = conn.transaction()?;
+
+    let mut user = User::load_id(&tx, PasswordReset::redeem(&tx, &reset_info.token)?)?;
+    user.set_password(&tx, &reset_info.new_password, &key.0)?;
+    Session::revoke_all(&tx, user.user_id)?;
+
+    Ok(tx.commit()?)
+}
+
+/// Returns the details of every session belonging to whatever user makes the
+/// request
+#[get("/sessions")]
+fn sessions(session: Session, db: State<DbConn>) -> Result<Json<Vec<SessionDetails>>, Error> {
+    let conn = db.lock().unwrap();
+    Ok(Json(Session::load_all(&conn, session.user_id, session.id)?))
+}
+
+/// Ends the session with the given id, which must belong to whatever user makes
+/// the request
+#[post("/revoke-session/<session_id>")]
+fn revoke_session(session: Session, db: State<DbConn>, session_id: u32) -> Result<(), Error> {
+    let conn = db.lock().unwrap();
+    Session::revoke(&conn, session.user_id, session_id)
+}
+
+/// Ends every session belonging to whatever user makes the request, including
+/// the one making it
+#[post("/revoke-all-sessions")]
+fn revoke_all_sessions(session: Session, mut cookies: Cookies, db: State<DbConn>) -> Result<(), Error> {
+    Session::revoke_all(&db.lock().unwrap(), session.user_id)?;
+
+    cookies.remove_private(Cookie::named(SESSION_COOKIE));
+    Ok(())
+}
+
+/// Changes the names and / or profile details of whatever user makes the request
+#[post("/update-profile", format = "json", data = "<update>")]
+fn update_profile(mut user: User, db: State<DbConn>, update: Json<ProfileUpdateInfo>) -> Result<Json<UserInfo>, Error> {
+    let conn = db.lock().unwrap();
+    user.update_profile(&conn, &update)?;
+
+    Ok(Json(user.into()))
+}
+
+/// Sets the provided image as the profile picture of whatever user makes the
+/// request, optionally cropping it first
+#[post("/set-profile-pic?<crop..>", format = "binary", data = "<data>")]
+fn set_profile_pic(
+    mut user: User,
+    db: State<DbConn>,
+    media: State<MediaStore>,
+    data: Data,
+    crop: Option<Form<Crop>>
+) -> Result<(), Error> {
+    let mut data_buf = vec![];
+
+    // 10 MB limit
+    data.open().take(10_485_760).read_to_end(&mut data_buf).map_err(|_| Error::ImageUploadFailed)?;
+    let image = match crop {
+        Some(crop) => ProcessedImage::cropped(data_buf, &crop)?,
+        None => ProcessedImage::new(data_buf)?
+    };
+
+    user.set_profile_pic(&db.lock().unwrap(), &media, &image)
+}
+
+/// Goes back to the generated default profile picture for whatever user makes
+/// the request
+#[post("/reset-profile-pic")]
+fn reset_profile_pic(mut user: User, db: State<DbConn>, media: State<MediaStore>) -> Result<(), Error> {
+    let image = User::identicon(&user.display_name, &user.email, &user.real_name)?;
+    user.set_profile_pic(&db.lock().unwrap(), &media, &image)
+}
+
+/// Returns the cover photo of the requested user id at the requested size,
+/// defaulting to full size
+#[get("/cover-photo/<req_user_id>?<size>")]
+fn cover_photo(
+    user: User,
+    db: State<DbConn>,
+    media: State<MediaStore>,
+    req_user_id: u32,
+    size: Option<RenditionSize>
+) -> Result<CachedFile, Error> {
+    let (content_type, hash) = {
+        let conn = db.lock().unwrap();
+        let original_hash = ProfileDetails::cover_photo_hash(&conn, req_user_id, user.user_id)?
+            .ok_or(Error::NotFound)?;
+        let hash = media.rendition_hash(&conn, &original_hash, size.unwrap_or(RenditionSize::Full))?;
+        (media.content_type(&conn, &hash)?, hash)
+    };
+
+    Ok(media.open_cached(content_type, hash)?.always_revalidate())
+}
+
+/// Sets the provided image as the cover photo of whatever user makes the
+/// request, optionally cropping it first
+#[post("/set-cover-photo?<crop..>", format = "binary", data = "<data>")]
+fn set_cover_photo(
+    user: User,
+    db: State<DbConn>,
+    media: State<MediaStore>,
+    data: Data,
+    crop: Option<Form<Crop>>
+) -> Result<(), Error> {
+    let mut data_buf = vec![];
+
+    // 10 MB limit
+    data.open().take(10_485_760).read_to_end(&mut data_buf).map_err(|_| Error::ImageUploadFailed)?;
+    let image = match crop {
+        Some(crop) => ProcessedImage::cropped(data_buf, &crop)?,
+        None => ProcessedImage::new(data_buf)?
+    };
+
+    ProfileDetails::set_cover_photo(&db.lock().unwrap(), &media, user.user_id, Some(&image))
+}
+
+/// Removes the cover photo of whatever user makes the request
+#[post("/remove-cover-photo")]
+fn remove_cover_photo(user: User, db: State<DbConn>, media: State<MediaStore>) -> Result<(), Error> {
+    ProfileDetails::set_cover_photo(&db.lock().unwrap(), &media, user.user_id, None)
+}
+
+/// Creates a new invite code
+#[post("/create-invite", format = "json", data = "<info>")]
+fn create_invite(
+    admin: Admin,
+    db: State<DbConn>,
+    info: Json<InviteCreationInfo>
+) -> Result<status::Created<Json<InviteCreationResponse>>, Error> {
+    let conn = db.lock().unwrap();
+    let code = Invite::create_new(&conn, admin.0.user_id, &info)?;
+
+    Ok(status::Created("".to_string(), Some(Json(InviteCreationResponse { code }))))
+}
+
+/// Returns all invite codes, including used up and revoked ones
+#[get("/invites")]
+fn invites(_admin: Admin, db: State<DbConn>) -> Result<Json<Vec<InviteDetails>>, Error> {
+    let conn = db.lock().unwrap();
+    Ok(Json(Invite::load_all(&conn)?))
+}
+
+/// Stops the given invite code from being used again
+#[post("/revoke-invite/<code>")]
+fn revoke_invite(_admin: Admin, db: State<DbConn>, code: String) -> Result<(), Error> {
+    let conn = db.lock().unwrap();
+    Invite::revoke(&conn, &code)
+}
+
+#[post("/me")]
+fn me_authed(user: User) -> Json<UserInfo> {
+    Json(user.into())
+}
+
+#[post("/me", rank = 2)]
+fn me() -> status::Custom<()> {
+    status::Custom(Status::Unauthorized, ())
+}
+
+/// A "catch-all" to redirect path requests to the index since we are building a SPA
+// TODO: perhaps we should still return a 404 for anything that's not a path?
+#[catch(404)]
+fn not_found() -> NamedFile {
+    NamedFile::open(Path::new(concat!(root_dir!(), "/svelte-app/public/index.html"))).unwrap()
+}
+
+/// Generates a random string of letters and numbers of the given length that is
+/// suitable for use as a secret.
+fn generate_token(len: usize) -> String {
+    thread_rng().sample_iter(&Alphanumeric).take(len).collect()
+}
+
+/// Hashes the given password for storage.
+///
+/// The `key` parameter is the secret key given to argon for hashing
+fn hash_password(password: &str, key: &str) -> Result<String, Error> {
+    let mut hasher = Hasher::default();
+    Ok(
+        hasher
+            .with_password(password)
+            .with_secret_key(key)
+            .hash()?
+    )
+}
+
+/// Returns true if the given password hashes to the given hash.
+///
+/// The `key` parameter is the secret key given to argon for hashing
+fn verify_password(hash: &str, password: &str, key: &str) -> Result<bool, Error> {
+    let mut verifier = Verifier::default();
+    Ok(
+        verifier
+            .with_hash(hash)
+            .with_password(password)
+            .with_secret_key(key)
+            .verify()?
+    )
+}
+
+/// Returns true if the given character is an emoji on its own, or becomes one
+/// when followed by a variation selector.
+///
+/// Regional indicators and skin tone modifiers only make emoji in combination
+/// with other characters, so they aren't included.
+fn is_emoji_base(c: char) -> bool {
+    match c as u32 {
+        0x1F1E6..=0x1F1FF | 0x1F3FB..=0x1F3FF => false,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2300..=0x23FF | 0x2B00..=0x2BFF |
+        0x2190..=0x21FF | 0x25A0..=0x25FF | 0x2934 | 0x2935 | 0x3030 | 0x303D |
+        0x3297 | 0x3299 | 0x00A9 | 0x00AE | 0x203C | 0x2049 | 0x2122 | 0x2139 |
+        0x24C2 => true,
+        _ => false
+    }
+}
+
+/// Returns true if the given string is exactly one emoji.
+///
+/// Flags, keycaps, skin tones and sequences joined with zero width joiners
+/// (like families) each count as one emoji.
+fn is_single_emoji(s: &str) -> bool {
+    let chars = s.chars().collect::<Vec<_>>();
+    let regional_indicator = |c: char| ('\u{1F1E6}'..='\u{1F1FF}').contains(&c);
+    let modifier = |c: char| {
+        // variation selector, skin tones and the tags used by subdivision flags
+        c == '\u{FE0F}' || ('\u{1F3FB}'..='\u{1F3FF}').contains(&c) || ('\u{E0020}'..='\u{E007F}').contains(&c)
+    };
+
+    if chars.is_empty() || chars.len() > 16 {
+        return false;
+    }
+
+    if chars.len() == 2 && chars.iter().all(|&c| regional_indicator(c)) {
+        return true;
+    }
+
+    match chars.as_slice() {
+        [base, '\u{20E3}'] | [base, '\u{FE0F}', '\u{20E3}'] if base.is_ascii_digit() || *base == '#' || *base == '*' => {
+            return true;
+        },
+        _ => {}
+    }
+
+    chars.split(|&c| c == '\u{200D}').all(|element| match element.split_first() {
+        Some((&base, modifiers)) => is_emoji_base(base) && modifiers.iter().all(|&c| modifier(c)),
+        None => false
+    })
+}
+
+/// Returns true if the given table has a column with the given name.
+fn column_exists(conn: &Connection, table: &str, column: &str) -> Result<bool, Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let name_iter = stmt.query_map(params![], |row| row.get::<_, String>(1))?;
+
+    for name in name_iter {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Adds a column to the given table if it does not already have one with that
+/// name.
+///
+/// Used to bring databases created by older versions of the server up to date.
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<(), Error> {
+    if !column_exists(conn, table, column)? {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), params![])?;
+    }
+
+    Ok(())
+}
+
+/// Recreates the given table using its current definition, keeping the given
+/// columns of every row.
+///
+/// SQLite can't drop columns, so this is how old columns get removed.
+fn rebuild_table(
+    conn: &Connection,
+    table: &str,
+    columns: &str,
+    create_table: fn(&Connection) -> Result<(), Error>
+) -> Result<(), Error> {
+    // SQLite reports a row for renames, which `execute` treats as an error
+    conn.execute_batch(&format!("ALTER TABLE {0} RENAME TO {0}_old", table))?;
+    create_table(conn)?;
+    conn.execute(
+        &format!("INSERT INTO {0} ({1}) SELECT {1} FROM {0}_old", table, columns),
+        params![]
+    )?;
+    conn.execute(&format!("DROP TABLE {}_old", table), params![])?;
+
+    Ok(())
+}
+
+/// Moves images stored in the database by older versions of the server into
+/// the given media store.
+///
+/// Does nothing if there is nothing left to move.
+fn migrate_blobs_to_media_store(conn: &mut Connection, media: &MediaStore) -> Result<(), Error> {
+    let tx = conn.transaction()?;
+    let migrated = column_exists(&tx, "user", "profile_pic")?
+        || column_exists(&tx, "post_image", "data")?
+        || column_exists(&tx, "post", "image")?;
+
+    if column_exists(&tx, "user", "profile_pic")? {
+        add_column_if_missing(&tx, "user", "profile_pic_hash", "TEXT")?;
+
+        let pics = {
+            let mut stmt = tx.prepare("SELECT user_id, profile_pic FROM user")?;
+            let pic_iter = stmt.query_map(params![], |row| Ok((row.get::<_, u32>(0)?, row.get::<_, Vec<u8>>(1)?)))?;
+            pic_iter.collect::<Result<Vec<_>, _>>()?
+        };
+
+        for (user_id, data) in pics {
+            let hash = media.store(&tx, &data)?;
+            tx.execute("UPDATE user SET profile_pic_hash=?1 WHERE user_id=?2", params![hash, user_id])?;
+        }
+
+        rebuild_table(&tx, "user", USER_COLUMNS, User::create_table)?;
+    }
+
+    if column_exists(&tx, "post_image", "data")? {
+        add_column_if_missing(&tx, "post_image", "hash", "TEXT")?;
+
+        let images = {
+            let mut stmt = tx.prepare("SELECT id, data FROM post_image")?;
+            let image_iter = stmt.query_map(params![], |row| Ok((row.get::<_, u32>(0)?, row.get::<_, Vec<u8>>(1)?)))?;
+            image_iter.collect::<Result<Vec<_>, _>>()?
+        };
+
+        for (image_id, data) in images {
+            let hash = media.store(&tx, &data)?;
+            tx.execute("UPDATE post_image SET hash=?1 WHERE id=?2", params![hash, image_id])?;
+        }
+
+        rebuild_table(&tx, "post_image", "id, post_id, position, hash, created_at", PostImage::create_table)?;
+    }
+
+    // posts used to have a single image stored alongside them
+    if column_exists(&tx, "post", "image")? {
+        let images = {
+            let mut stmt = tx.prepare("SELECT id, image, created_at FROM post WHERE image IS NOT NULL")?;
+            let image_iter = stmt.query_map(params![], |row| Ok((
+                row.get::<_, u32>(0)?,
+                row.get::<_, Vec<u8>>(1)?,
+                row.get::<_, NaiveDateTime>(2)?
+            )))?;
+            image_iter.collect::<Result<Vec<_>, _>>()?
+        };
+
+        for (post_id, data, created_at) in images {
+            let hash = media.store(&tx, &data)?;
+            tx.execute(
+                "INSERT INTO post_image (post_id, position, hash, created_at)
+                        VALUES (?1, 0, ?2, ?3)",
+                params![post_id, hash, created_at],
+            )?;
+        }
+
+        rebuild_table(&tx, "post", "id, body, created_at, user_id, edited_at", Post::create_table)?;
+    }
+
+    tx.commit()?;
+
+    if migrated {
+        // give back the space the images took up in the database file
+        conn.execute("VACUUM", params![])?;
+    }
+
+    Ok(())
+}
+
+/// Renders any stored images that don't have renditions yet, such as those
+/// stored by older versions of the server.
+fn generate_missing_renditions(conn: &Connection, media: &MediaStore) -> Result<(), Error> {
+    let originals = {
+        let mut stmt = conn.prepare("SELECT hash FROM post_image UNION SELECT profile_pic_hash FROM user")?;
+        let original_iter = stmt.query_map(params![], |row| row.get::<_, String>(0))?;
+        original_iter.collect::<Result<Vec<_>, _>>()?
+    };
+
+    for hash in originals {
+        if media.has_renditions(conn, &hash)? {
+            continue;
+        }
+
+        let mut data = vec![];
+        media.open(&hash)?.read_to_end(&mut data)?;
+        media.add_renditions(conn, &hash, &ProcessedImage::new(data)?)?;
+    }
+
+    Ok(())
+}
+
+/// Performs any necessary database setup upon application start.
+///
+/// Can be called multiple times without issue.
+fn init_database(conn: &Connection) -> Result<(), Error> {
+    MediaStore::create_table(conn)?;
+    User::create_table(conn)?;
+    ProfileDetails::create_table(conn)?;
+    Invite::create_table(conn)?;
+    Session::create_table(conn)?;
+    PasswordReset::create_table(conn)?;
+    Post::create_table(conn)?;
+    PostImage::create_table(conn)?;
+    Comment::create_table(conn)?;
+    ReactionTarget::create_table(conn)
+}
+
+/// Builds and returns the search schema.
+fn init_search_schema() -> Schema {
+    let mut schema_builder = Schema::builder();
+
+    schema_builder.add_text_field("body", TEXT);
+    schema_builder.add_u64_field("post_id", INDEXED | STORED);
+    schema_builder.add_u64_field("user_id", INDEXED);
+    schema_builder.add_date_field("created_at", INDEXED);
+
+    schema_builder.build()
+}
+
+/// Builds the rocket instance that serves the app.
+///
+/// If no mailer is given, one is built from the config.
+fn rocket(
+    mut conn: Connection,
+    index: Index,
+    schema: Schema,
+    media: MediaStore,
+    mailer: Option<Box<dyn Mailer>>
+) -> Result<rocket::Rocket, Error> {
+    init_database(&conn)?;
+    migrate_blobs_to_media_store(&mut conn, &media)?;
+    media.fill_missing_content_types(&conn)?;
+    generate_missing_renditions(&conn, &media)?;
+
+    #[cfg(feature = "deployable")]
+    let static_files_dir = concat!(root_dir!(), "static");
+    #[cfg(not(feature = "deployable"))]
+    let static_files_dir = concat!(root_dir!(), "/svelte-app/public");
+
+    Ok(
+        rocket::ignite()
+            // TODO: use a rwlock here?
+            .manage(Mutex::new(conn))
+            // search index writer with 50 MB heap
+            .manage(Mutex::new(index.writer(50_000_000)?))
+            .manage(index.reader_builder()
+                .reload_policy(ReloadPolicy::OnCommit)
+                .try_into()?
+            )
+            .manage(schema)
+            .manage(media)
+            .attach(AdHoc::on_attach("Argon Secret Key", |rocket| {
+                // TODO: right now this key gets printed by rocket to the console
+                // every time the binary is launched which is kind of annoying
+                let key = rocket.config()
+                    .get_str("argon_secret_key")
+                    .unwrap()
+                    .to_string();
+                let dummy_hash = match hash_password(&generate_token(32), &key) {
+                    Ok(hash) => hash,
+                    Err(e) => {
+                        error!("Error while hashing the dummy password: {:?}", e);
+                        return Err(rocket);
+                    }
+                };
+    
+                Ok(rocket.manage(ArgonSecretKey(key)).manage(DummyHash(dummy_hash)))
+            }))
+            .attach(AdHoc::on_attach("Session Config", |rocket| {
+                let idle_timeout_hours = rocket.config()
+                    .get_int("session_idle_timeout_hours")
+                    .unwrap_or(24 * 14);
+                let max_age_hours = rocket.config()
+                    .get_int("session_max_age_hours")
+                    .unwrap_or(24 * 90);
+
+                Ok(rocket.manage(SessionConfig {
+                    idle_timeout: chrono::Duration::hours(idle_timeout_hours),
+                    max_age: chrono::Duration::hours(max_age_hours)
+                }))
+            }))
+            .attach(AdHoc::on_attach("Mailer", move |rocket| {
+                let mailer = match mailer {
+                    Some(mailer) => Ok(mailer),
+                    None => mailer::from_config(rocket.config(), Path::new(concat!(root_dir!(), "/mail")))
+                };
+
+                match mailer {
+                    Ok(mailer) => Ok(rocket.manage(mailer)),
+                    Err(e) => {
+                        error!("Error while setting up the mailer: {:?}", e);
+                        Err(rocket)
+                    }
+                }
+            }))
+            .attach(AdHoc::on_attach("Public URL", |rocket| {
+                let public_url = match rocket.config().get_str("public_url") {
+                    Ok(url) => url.trim_end_matches('/').to_string(),
+                    Err(_) => format!("http://{}:{}", rocket.config().address, rocket.config().port)
+                };
+
+                Ok(rocket.manage(PublicUrl(public_url)))
+            }))
+            .attach(AdHoc::on_attach("Invite Only", |rocket| {
+                let invite_only = rocket.config()
+                    .get_bool("invite_only")
+                    .unwrap_or(true);
+
+                Ok(rocket.manage(InviteOnly(invite_only)))
+            }))
+            // TODO: bundle static files into binary for easy deploy?
+            // TODO: make a crate to manage boilerplate for serving static files from
+            // a hashmap generated at compile time?
+            .mount("/", StaticFiles::from(static_files_dir))
+            .mount("/api", routes![
+                signup,
+                login,
+                logout,
+                change_password,
+                request_password_reset,
+                reset_password,
+                sessions,
+                revoke_session,
+                revoke_all_sessions,
+                me,
+                me_authed,
+                profile_pic,
+                update_profile,
+                set_profile_pic,
+                reset_profile_pic,
+                cover_photo,
+                set_cover_photo,
+                remove_cover_photo,
+                user_info,
+                users,
+                create_post,
+                edit_post,
+                delete_post,
+                add_post_image,
+                set_post_image,
+                reorder_post_images,
+                remove_post_image,
+                post_image,
+                recent_posts,
+                search_posts,
+                create_comment,
+                post_comments,
+                edit_comment,
+                delete_comment,
+                add_reaction,
+                remove_reaction,
+                reactions
+            ])
+            .mount("/api/admin", routes![
+                create_invite,
+                invites,
+                revoke_invite
+            ])
+            .register(catchers![not_found])
+    )
+}
+
+fn main() -> Result<(), Error> {
+    let search_index_dir = Path::new(concat!(root_dir!(), "/search_index"));
+    if !search_index_dir.exists() {
+        create_dir(&search_index_dir)?;
+    }
+
+    let index_dir = MmapDirectory::open(&search_index_dir)?;
+    let search_schema = init_search_schema();
+
+    // TODO: more configurable database persistence?
+    // rocket pretty prints the error when it drops if one occurs
+    let _ = rocket(
+        Connection::open(concat!(root_dir!(), "/db.db3"))?,
+        Index::open_or_create(index_dir, search_schema.clone())?,
+        search_schema,
+        MediaStore::new(concat!(root_dir!(), "/media"))?,
+        None
+    )?.launch();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rocket::Response;
+    use rocket::http::{ContentType, Header};
+    use rocket::local::Client;
+    use std::fs::File;
+    use image::GenericImageView;
+    use tempfile::{tempdir, TempDir};
+
+    fn session_cookie(response: &Response) -> Option<Cookie<'static>> {
+        let cookie = response.headers()
+            .get("Set-Cookie")
+            .find(|v| v.starts_with(SESSION_COOKIE))
+            .and_then(|val| Cookie::parse_encoded(val).ok());
+    
+        cookie.map(|c| c.into_owned())
+    }
+
+    fn login(client: &Client, email: String, password: String) -> Option<Cookie<'static>> {
+        let login_info = LoginInfo {
+            email,
+            password
+        };
+
+        let response = client.post("/api/login")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&login_info).unwrap())
+            .dispatch();
+
+        session_cookie(&response)
+    }
+
+    fn create_dummy_user(conn: &Connection, media: &MediaStore, email: String, password: String, key: String) -> Result<(), Error> {
+        let rinfo = RegisterInfo {
+            real_name: format!("Some Dummy <{}>", email),
+            email,
+            password,
+            display_name: "dummy".to_string(),
+            invite_code: None
+        };
+
+        User::create_new(&conn, media, &rinfo, &key)
+    }
+
+    /// Builds a client for a fresh instance of the app, returning it along
+    /// with the directories its media and emails are written to
+    fn test_client() -> Result<(Client, TempDir, TempDir), Error> {
+        let conn = Connection::open_in_memory()?;
+        let schema = init_search_schema();
+        let media_dir = tempdir()?;
+        let mail_dir = tempdir()?;
+
+        let client = Client::untracked(rocket(
+            conn,
+            Index::create_in_ram(schema.clone()),
+            schema,
+            MediaStore::new(media_dir.path())?,
+            Some(Box::new(mailer::FileMailer::new(mail_dir.path())))
+        )?).unwrap();
+
+        Ok((client, media_dir, mail_dir))
+    }
+
+    #[test]
+    fn test_user_database() -> Result<(), Error> {
+        let conn = Connection::open_in_memory()?;
+        init_database(&conn)?;
+
+        let email = String::from("some_email@gmail.com");
+        let display_name = String::from("display_name");
+        let real_name = String::from("real_name");
+
+        let identicon = Identicon::new_default(&(display_name.clone() + &email + &real_name));
+        let data = identicon.export_file_data(ImageType::PNG);
+        let dir = tempdir()?;
+        let media = MediaStore::new(dir.path())?;
+
+        let me = User {
+            user_id: 1,
+            hash: "kalnfdanf".to_string(),
+            email,
+            created_at: Utc::now().naive_utc(),
+            display_name,
+            real_name,
+            profile_pic_hash: media.store(&conn, &data)?
+        };
+        conn.execute(
+            "INSERT INTO user (user_id, hash, email, created_at, display_name, real_name, profile_pic_hash)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![me.user_id, me.hash, me.email, me.created_at, me.display_name, me.real_name, me.profile_pic_hash],
+        )?;
+
+        let user = User::load_id(&conn, 1)?;
+        assert_eq!(&user, &me);
+
+        let user = User::load_email(&conn, &me.email)?;
+        assert_eq!(&user, &me);
+
+        let profile_pic_hash = User::profile_pic_hash(&conn, 1)?;
+        assert_eq!(&me.profile_pic_hash, &profile_pic_hash);
+
+        let mut profile_pic = vec![];
+        media.open(&profile_pic_hash)?.read_to_end(&mut profile_pic)?;
+        assert_eq!(profile_pic, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_user_from_info() -> Result<(), Error> {
+        let conn = Connection::open_in_memory()?;
+        init_database(&conn)?;
+
+        let secret_key = "test secret key";
+        let password = "myAmazingPassw0rd!".to_string();
+        let email = "some_email@gmail.com".to_string();
+        let dir = tempdir()?;
+        let media = MediaStore::new(dir.path())?;
+        create_dummy_user(&conn, &media, email.clone(), password.clone(), secret_key.to_string())?;
+
+        let user = User::load_id(&conn, 1)?;
+        assert_eq!(user.auth(&LoginInfo { email: email.clone(), password: password.clone() }, secret_key)?, true);
+        assert_eq!(user.auth(&LoginInfo { email: "adifferentemail@gmail.com".to_string(), password }, secret_key)?, false);
+        assert_eq!(user.auth(&LoginInfo { email, password: "thisisthewrongpassword".to_string() }, secret_key)?, false);
+        assert_eq!(user.auth(&LoginInfo { email: "anotheremail@gmail.com".to_string(), password: "83quhejwdknas".to_string() }, secret_key)?, false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_invalid_id() -> Result<(), Error> {
+        let conn = Connection::open_in_memory()?;
+        init_database(&conn)?;
+
+        let res = User::load_id(&conn, 1);
+
+        // error should be "QueryReturnedNoRows"
+        // TODO: re-add this more precise test with some sort of assert_matches
+        // assert_eq!(res, Err(Error::DatabaseErr(rusqlite::Error::QueryReturnedNoRows)));
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn create_with_existing_email() -> Result<(), Error> {
+        let conn = Connection::open_in_memory()?;
+        init_database(&conn)?;
+        let secret_key = "test key";
+        let email = "anemail@gmail.com".to_string();
+
+        let register_info_1 = RegisterInfo {
+            email: email.clone(),
+            password: "aofsaff".to_string(),
+            display_name: "name".to_string(),
+            real_name: "realname".to_string(),
+            invite_code: None
+        };
+
+        let register_info_2 = RegisterInfo {
+            email,
+            password: "lmzioiofa".to_string(),
+            display_name: "name2".to_string(),
+            real_name: "realname2".to_string(),
+            invite_code: None
+        };
+
+        let dir = tempdir()?;
+        let media = MediaStore::new(dir.path())?;
+        User::create_new(&conn, &media, &register_info_1, secret_key)?;
+        assert!(User::create_new(&conn, &media, &register_info_2, secret_key).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_with_existing_real_name() -> Result<(), Error> {
+        let conn = Connection::open_in_memory()?;
+        init_database(&conn)?;
+        let secret_key = "test key";
+        let real_name = "arealname".to_string();
+
+        let register_info_1 = RegisterInfo {
+            email: "someemail@gmail.com".to_string(),
+            password: "aofsaff".to_string(),
+            display_name: "name".to_string(),
+            real_name: real_name.clone(),
+            invite_code: None
+        };
+
+        let register_info_2 = RegisterInfo {
+            email: "someotheremail@gmail.com".to_string(),
+            password: "lmzioiofa".to_string(),
+            display_name: "name2".to_string(),
+            real_name,
+            invite_code: None
+        };
+
+        let dir = tempdir()?;
+        let media = MediaStore::new(dir.path())?;
+        User::create_new(&conn, &media, &register_info_1, secret_key)?;
+        assert!(User::create_new(&conn, &media, &register_info_2, secret_key).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_with_existing_real_name_and_email() -> Result<(), Error> {
+        let conn = Connection::open_in_memory()?;
+        init_database(&conn)?;
+        let secret_key = "test key";
+        let real_name = "arealname".to_string();
+        let email = "someemail@gmail.com".to_string();
+
+        let register_info_1 = RegisterInfo {
+            email: email.clone(),
+            password: "aofsaff".to_string(),
+            display_name: "name".to_string(),
+            real_name: real_name.clone(),
+            invite_code: None
+        };
+
+        let register_info_2 = RegisterInfo {
+            email,
+            password: "lmzioiofa".to_string(),
+            display_name: "name2".to_string(),
+            real_name,
+            invite_code: None
+        };
+
+        let dir = tempdir()?;
+        let media = MediaStore::new(dir.path())?;
+        User::create_new(&conn, &media, &register_info_1, secret_key)?;
+        assert!(User::create_new(&conn, &media, &register_info_2, secret_key).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_post_set_image() -> Result<(), Error> {
+        let (client, _media_dir, _mail_dir) = test_client()?;
+        let db = client.rocket().state::<DbConn>().unwrap();
+        let media = client.rocket().state::<MediaStore>().unwrap();
+        let secret_key = client.rocket().state::<ArgonSecretKey>().unwrap();
+
+        let email = "some_email@gmail.com".to_string();
+        let password = "myAmazingPassw0rd!".to_string();
+        {
+            let conn = db.lock().unwrap();
+            create_dummy_user(&conn, &media, email.clone(), password.clone(), secret_key.0.clone())?;
+        }
+        let login_cookie = login(&client, email, password).expect("logged in");
+
+        let post_info = PostInfo {
+            body: "This is the body of a post!".to_string()
+        };
+
+        let mut response = client
+            .post("/api/create-post")
+            .cookie(login_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&post_info).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let response_json = response.body_string().unwrap();
+        let created_post: PostCreationResponse = serde_json::from_str(&response_json).unwrap();
+        assert_eq!(created_post.post_id, 1);
+
+        {
+            // doing this in a new block so the mutex lock drops and allows the
+            // second post to succeed
+            let conn = db.lock().unwrap();
+            let post = Post::load_id(&conn, 1)?;
+            assert!(PostImage::ids_for_post(&conn, 1)?.is_empty());
+            assert_eq!(&post.body, &post_info.body);
+            assert_eq!(post.user_id, 1);
+        }
+
+        let mut file = File::open(concat!(root_dir!(), "/svelte-app/public/favicon.png")).unwrap();
+        let mut favicon_buf = vec![];
+        file.read_to_end(&mut favicon_buf).unwrap();
+
+        let mut response = client
+            .post("/api/add-post-image/1")
+            .cookie(login_cookie.clone())
+            .header(ContentType::Binary)
+            .body(&favicon_buf)
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let created_image: PostImageCreationResponse = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        let conn = db.lock().unwrap();
+        assert_eq!(PostImage::ids_for_post(&conn, 1)?, vec![created_image.image_id]);
+
+        // the original upload is kept untouched
+        let image = PostImage::load_id(&conn, created_image.image_id)?;
+        let mut image_data = vec![];
+        media.open(&image.hash)?.read_to_end(&mut image_data)?;
+        assert_eq!(image_data, favicon_buf);
+        drop(conn);
+
+        let mut response = client
+            .get(format!("/api/post-image/{}?size=thumbnail", created_image.image_id))
+            .cookie(login_cookie.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        // the favicon is a PNG and should stay one
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+        let thumbnail = image::load_from_memory(&response.body_bytes().unwrap()).unwrap();
+        assert!(thumbnail.width() <= RenditionSize::Thumbnail.max_dimension());
+        assert!(thumbnail.height() <= RenditionSize::Thumbnail.max_dimension());
+
+        let etag = response.headers().get_one("ETag").unwrap().to_string();
+        assert!(response.headers().get_one("Cache-Control").unwrap().contains("immutable"));
+
+        // a browser that already has the image shouldn't get sent it again
+        let mut response = client
+            .get(format!("/api/post-image/{}?size=thumbnail", created_image.image_id))
+            .cookie(login_cookie.clone())
+            .header(Header::new("If-None-Match", etag))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotModified);
+        assert!(response.body_bytes().is_none());
+
+        // older clients upload with the route's old name
+        let response = client
+            .post("/api/set-post-image/1")
+            .cookie(login_cookie.clone())
+            .header(ContentType::Binary)
+            .body(&favicon_buf)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(PostImage::ids_for_post(&db.lock().unwrap(), 1)?.len(), 2);
+
+        // ids aren't reused, since browsers cache images by id
+        let last_id = *PostImage::ids_for_post(&db.lock().unwrap(), 1)?.last().unwrap();
+        let response = client
+            .post(format!("/api/remove-post-image/{}", last_id))
+            .cookie(login_cookie.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let mut response = client
+            .post("/api/add-post-image/1")
+            .cookie(login_cookie.clone())
+            .header(ContentType::Binary)
+            .body(&favicon_buf)
+            .dispatch();
+        let readded_image: PostImageCreationResponse = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert!(readded_image.image_id > last_id);
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_posts() -> Result<(), Error> {
+        let (client, _media_dir, _mail_dir) = test_client()?;
+        let db = client.rocket().state::<DbConn>().unwrap();
+        let media = client.rocket().state::<MediaStore>().unwrap();
+        let secret_key = client.rocket().state::<ArgonSecretKey>().unwrap();
+
+        let email = "some_email@gmail.com".to_string();
+        let password = "myAmazingPassw0rd!".to_string();
+        {
+            let conn = db.lock().unwrap();
+            create_dummy_user(&conn, &media, email.clone(), password.clone(), secret_key.0.clone())?;
+        }
+        let login_cookie = login(&client, email, password).expect("logged in");
+
+        let body_1 = "Unicorns are amazing!!!".to_string();
+        let body_2 = "Pink is the best color on the planet".to_string();
+        let body_3 = "Rokt leeg is best video game".to_string();
+        let body_4 = "Bunnies are pretty cool too I guess".to_string();
+        let body_5 = "But did I mention unicorns are amazing?!?!?!?".to_string();
+
+        let post_infos = vec![
+            PostInfo {
+                body: body_1.clone()
+            },
+            PostInfo {
+                body: body_2
+            },
+            PostInfo {
+                body: body_3.clone()
+            },
+            PostInfo {
+                body: body_4
+            },
+            PostInfo {
+                body: body_5.clone()
+            }
+        ];
+
+        for post_info in post_infos {
+            let response = client
+                .post("/api/create-post")
+                .cookie(login_cookie.clone())
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(&post_info).unwrap())
+                .dispatch();
+                assert_eq!(response.status(), Status::Created);
+        }
+
+        // the reader picks up commits in the background, so it's reloaded here
+        // to make sure the new posts can be searched straight away
+        client.rocket().state::<IndexReader>().unwrap().reload()?;
+
+        let mut response = client
+            .get("/api/search-posts?query_string=unicorns")
+            .cookie(login_cookie.clone())
+            .dispatch();
+
+        let response_json = response.body_string().unwrap();
+        let search_results: Vec<PostDetails> = serde_json::from_str(&response_json).unwrap();
+
+        assert!(
+            search_results[0].body == body_1 ||
+            search_results[1].body == body_1
+        );
+
+        assert!(
+            search_results[0].body == body_5 ||
+            search_results[1].body == body_5
+        );
+
+        let mut response = client
+            .get("/api/search-posts?query_string=rokt+leeg")
+            .cookie(login_cookie.clone())
+            .dispatch();
+
+        let response_json = response.body_string().unwrap();
+        let search_results: Vec<PostDetails> = serde_json::from_str(&response_json).unwrap();
+
+        assert!(search_results[0].body == body_3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn comments_on_post() -> Result<(), Error> {
+        let (client, _media_dir, _mail_dir) = test_client()?;
+        let db = client.rocket().state::<DbConn>().unwrap();
+        let media = client.rocket().state::<MediaStore>().unwrap();
+        let secret_key = client.rocket().state::<ArgonSecretKey>().unwrap();
+
+        let email = "some_email@gmail.com".to_string();
+        let password = "myAmazingPassw0rd!".to_string();
+        {
+            let conn = db.lock().unwrap();
+            create_dummy_user(&conn, &media, email.clone(), password.clone(), secret_key.0.clone())?;
+        }
+        let login_cookie = login(&client, email, password).expect("logged in");
+
+        let response = client
+            .post("/api/create-post")
+            .cookie(login_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&PostInfo { body: "A post".to_string() }).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+
+        let comment_infos = vec![
+            CommentInfo { body: "First!".to_string(), parent_id: None },
+            CommentInfo { body: "A reply".to_string(), parent_id: Some(1) },
+            // replying to a reply should attach to the top-level comment
+            CommentInfo { body: "A reply to a reply".to_string(), parent_id: Some(2) },
+            CommentInfo { body: "Second".to_string(), parent_id: None }
+        ];
+
+        for comment_info in comment_infos {
+            let response = client
+                .post("/api/create-comment/1")
+                .cookie(login_cookie.clone())
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(&comment_info).unwrap())
+                .dispatch();
+            assert_eq!(response.status(), Status::Created);
+        }
+
+        let response = client
+            .post("/api/create-comment/1")
+            .cookie(login_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&CommentInfo { body: "   ".to_string(), parent_id: None }).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let mut response = client
+            .get("/api/post-comments/1?n=1")
+            .cookie(login_cookie.clone())
+            .dispatch();
+        let comments: Vec<CommentDetails> = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(comments.len(), 1);
+        assert_eq!(&comments[0].body, "First!");
+        assert_eq!(comments[0].replies.len(), 2);
+        assert!(comments[0].replies.iter().all(|c| c.parent_id == Some(1)));
+
+        let mut response = client
+            .get("/api/post-comments/1?n=10&offset=1")
+            .cookie(login_cookie.clone())
+            .dispatch();
+        let comments: Vec<CommentDetails> = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(comments.len(), 1);
+        assert_eq!(&comments[0].body, "Second");
+
+        let mut response = client
+            .get("/api/recent-posts?n=1")
+            .cookie(login_cookie.clone())
+            .dispatch();
+        let posts: Vec<PostDetails> = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(posts[0].comment_count, 4);
+
+        let response = client
+            .post("/api/edit-comment/4")
+            .cookie(login_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&CommentEditInfo { body: "Second (edited)".to_string() }).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        {
+            let conn = db.lock().unwrap();
+            let comment = Comment::load_id(&conn, 4)?;
+            assert_eq!(&comment.body, "Second (edited)");
+            assert!(comment.edited_at.is_some());
+        }
+
+        let response = client
+            .post("/api/delete-comment/1")
+            .cookie(login_cookie.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let conn = db.lock().unwrap();
+        // the replies should have been deleted along with their parent
+        assert!(Comment::load_id(&conn, 2).is_err());
+        assert!(Comment::load_id(&conn, 3).is_err());
+        assert_eq!(Post::load_info(&conn, 1, 1)?.comment_count, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reactions_on_posts_and_comments() -> Result<(), Error> {
+        let (client, _media_dir, _mail_dir) = test_client()?;
+        let db = client.rocket().state::<DbConn>().unwrap();
+        let media = client.rocket().state::<MediaStore>().unwrap();
+        let secret_key = client.rocket().state::<ArgonSecretKey>().unwrap();
+
+        let password = "myAmazingPassw0rd!".to_string();
+        {
+            let conn = db.lock().unwrap();
+            create_dummy_user(&conn, &media, "user_1@gmail.com".to_string(), password.clone(), secret_key.0.clone())?;
+            create_dummy_user(&conn, &media, "user_2@gmail.com".to_string(), password.clone(), secret_key.0.clone())?;
+        }
+        let cookie_1 = login(&client, "user_1@gmail.com".to_string(), password.clone()).expect("logged in");
+        let cookie_2 = login(&client, "user_2@gmail.com".to_string(), password).expect("logged in");
+
+        client
+            .post("/api/create-post")
+            .cookie(cookie_1.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&PostInfo { body: "React to me".to_string() }).unwrap())
+            .dispatch();
+        client
+            .post("/api/create-comment/1")
+            .cookie(cookie_1.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&CommentInfo { body: "And me".to_string(), parent_id: None }).unwrap())
+            .dispatch();
+
+        let reactions = vec![
+            (&cookie_1, "/api/add-reaction/post/1", "👍"),
+            // reacting twice with the same emoji should only count once
+            (&cookie_1, "/api/add-reaction/post/1", "👍"),
+            (&cookie_2, "/api/add-reaction/post/1", "👍"),
+            (&cookie_2, "/api/add-reaction/post/1", "🎉"),
+            (&cookie_2, "/api/add-reaction/comment/1", "❤️")
+        ];
+
+        for (cookie, path, emoji) in reactions {
+            let response = client
+                .post(path)
+                .cookie(cookie.clone())
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(&ReactionInfo { emoji: emoji.to_string() }).unwrap())
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+        }
+
+        let response = client
+            .post("/api/add-reaction/post/2")
+            .cookie(cookie_1.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&ReactionInfo { emoji: "👍".to_string() }).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let mut response = client
+            .get("/api/recent-posts?n=1")
+            .cookie(cookie_1.clone())
+            .dispatch();
+        let posts: Vec<PostDetails> = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        let reactions = &posts[0].reactions;
+        assert_eq!(reactions.len(), 2);
+        assert_eq!((reactions[0].emoji.as_str(), reactions[0].count, reactions[0].reacted), ("👍", 2, true));
+        assert_eq!((reactions[1].emoji.as_str(), reactions[1].count, reactions[1].reacted), ("🎉", 1, false));
+
+        let mut response = client
+            .get("/api/post-comments/1?n=10")
+            .cookie(cookie_2.clone())
+            .dispatch();
+        let comments: Vec<CommentDetails> = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(comments[0].reactions.len(), 1);
+        assert!(comments[0].reactions[0].reacted);
+
+        let response = client
+            .post("/api/remove-reaction/post/1")
+            .cookie(cookie_1.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&ReactionInfo { emoji: "👍".to_string() }).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let mut response = client
+            .get("/api/reactions/post/1")
+            .cookie(cookie_1.clone())
+            .dispatch();
+        let reactions: Vec<ReactionUserInfo> = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(reactions.len(), 2);
+        assert!(reactions.iter().all(|r| r.user.user_id == 2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reactions_are_single_emoji() {
+        let valid = ["👍", "❤️", "☺", "🇳🇿", "🏴\u{E0067}\u{E0062}\u{E0065}\u{E006E}\u{E0067}\u{E007F}", "👍🏽", "1️⃣", "#⃣", "👩‍👩‍👧", "🧑🏻‍🤝‍🧑🏿"];
+        for emoji in &valid {
+            assert!(is_single_emoji(emoji), "{}", emoji);
+        }
+
+        let invalid = ["", "a", "ok", "👍👍", "👍 ", "🇳", "🏽", "‍👍", "👍‍", "11️⃣", "lol😂"];
+        for emoji in &invalid {
+            assert!(!is_single_emoji(emoji), "{}", emoji);
+        }
+    }
+
+    #[test]
+    fn edit_and_delete_posts() -> Result<(), Error> {
+        let (client, _media_dir, _mail_dir) = test_client()?;
+        let db = client.rocket().state::<DbConn>().unwrap();
+        let media = client.rocket().state::<MediaStore>().unwrap();
+        let secret_key = client.rocket().state::<ArgonSecretKey>().unwrap();
+
+        let email = "some_email@gmail.com".to_string();
+        let password = "myAmazingPassw0rd!".to_string();
+        {
+            let conn = db.lock().unwrap();
+            create_dummy_user(&conn, &media, email.clone(), password.clone(), secret_key.0.clone())?;
+        }
+        let login_cookie = login(&client, email, password).expect("logged in");
+
+        for body in &["Unicorns are amazing!!!", "Bunnies are pretty cool too I guess"] {
+            let response = client
+                .post("/api/create-post")
+                .cookie(login_cookie.clone())
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(&PostInfo { body: (*body).to_string() }).unwrap())
+                .dispatch();
+            assert_eq!(response.status(), Status::Created);
+        }
+
+        client
+            .post("/api/create-comment/2")
+            .cookie(login_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&CommentInfo { body: "So cool".to_string(), parent_id: None }).unwrap())
+            .dispatch();
+
+        let response = client
+            .post("/api/edit-post/1")
+            .cookie(login_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&PostInfo { body: "Dragons are amazing!!!".to_string() }).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .post("/api/delete-post/2")
+            .cookie(login_cookie.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        // see the comment in the `search_posts` test
+        client.rocket().state::<IndexReader>().unwrap().reload()?;
+
+        let search = |query: &str| -> Vec<PostDetails> {
+            let mut response = client
+                .get(format!("/api/search-posts?query_string={}", query))
+                .cookie(login_cookie.clone())
+                .dispatch();
+
+            serde_json::from_str(&response.body_string().unwrap()).unwrap()
+        };
+
+        assert!(search("unicorns").is_empty());
+        assert!(search("bunnies").is_empty());
+
+        let results = search("dragons");
+        assert_eq!(results.len(), 1);
+        assert_eq!(&results[0].body, "Dragons are amazing!!!");
+        assert!(results[0].edited_at.is_some());
+
+        let conn = db.lock().unwrap();
+        assert!(!Post::exists(&conn, 2)?);
+        assert!(Comment::load_id(&conn, 1).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cannot_modify_other_users_content() -> Result<(), Error> {
+        let (client, _media_dir, _mail_dir) = test_client()?;
+        let db = client.rocket().state::<DbConn>().unwrap();
+        let media = client.rocket().state::<MediaStore>().unwrap();
+        let secret_key = client.rocket().state::<ArgonSecretKey>().unwrap();
+
+        let password = "myAmazingPassw0rd!".to_string();
+        {
+            let conn = db.lock().unwrap();
+            create_dummy_user(&conn, &media, "owner@gmail.com".to_string(), password.clone(), secret_key.0.clone())?;
+            create_dummy_user(&conn, &media, "intruder@gmail.com".to_string(), password.clone(), secret_key.0.clone())?;
+        }
+        let owner_cookie = login(&client, "owner@gmail.com".to_string(), password.clone()).expect("logged in");
+        let intruder_cookie = login(&client, "intruder@gmail.com".to_string(), password).expect("logged in");
+
+        client
+            .post("/api/create-post")
+            .cookie(owner_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&PostInfo { body: "My post".to_string() }).unwrap())
+            .dispatch();
+        client
+            .post("/api/create-comment/1")
+            .cookie(owner_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&CommentInfo { body: "My comment".to_string(), parent_id: None }).unwrap())
+            .dispatch();
+
+        let mut file = File::open(concat!(root_dir!(), "/svelte-app/public/favicon.png")).unwrap();
+        let mut favicon_buf = vec![];
+        file.read_to_end(&mut favicon_buf).unwrap();
+
+        let response = client
+            .post("/api/add-post-image/1")
+            .cookie(intruder_cookie.clone())
+            .header(ContentType::Binary)
+            .body(&favicon_buf)
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let response = client
+            .post("/api/edit-post/1")
+            .cookie(intruder_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&PostInfo { body: "Not your post".to_string() }).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let response = client
+            .post("/api/edit-comment/1")
+            .cookie(intruder_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&CommentEditInfo { body: "Not your comment".to_string() }).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let response = client
+            .post("/api/delete-comment/1")
+            .cookie(intruder_cookie.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let response = client
+            .post("/api/delete-post/1")
+            .cookie(intruder_cookie.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let response = client
+            .post("/api/delete-post/2")
+            .cookie(intruder_cookie.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let conn = db.lock().unwrap();
+        assert_eq!(&Post::load_id(&conn, 1)?.body, "My post");
+        assert!(PostImage::ids_for_post(&conn, 1)?.is_empty());
+        assert_eq!(&Comment::load_id(&conn, 1)?.body, "My comment");
+
+        Ok(())
+    }
+
+    #[test]
+    fn multiple_post_images() -> Result<(), Error> {
+        let (client, _media_dir, _mail_dir) = test_client()?;
+        let db = client.rocket().state::<DbConn>().unwrap();
+        let media = client.rocket().state::<MediaStore>().unwrap();
+        let secret_key = client.rocket().state::<ArgonSecretKey>().unwrap();
+
+        let email = "some_email@gmail.com".to_string();
+        let password = "myAmazingPassw0rd!".to_string();
+        {
+            let conn = db.lock().unwrap();
+            create_dummy_user(&conn, &media, email.clone(), password.clone(), secret_key.0.clone())?;
+        }
+        let login_cookie = login(&client, email, password).expect("logged in");
+
+        client
+            .post("/api/create-post")
+            .cookie(login_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&PostInfo { body: "Photos from the trip".to_string() }).unwrap())
+            .dispatch();
+
+        let mut file = File::open(concat!(root_dir!(), "/svelte-app/public/favicon.png")).unwrap();
+        let mut favicon_buf = vec![];
+        file.read_to_end(&mut favicon_buf).unwrap();
+
+        for _ in 0..3 {
+            let response = client
+                .post("/api/add-post-image/1")
+                .cookie(login_cookie.clone())
+                .header(ContentType::Binary)
+                .body(&favicon_buf)
+                .dispatch();
+            assert_eq!(response.status(), Status::Created);
+        }
+
+        let response = client
+            .post("/api/reorder-post-images/1")
+            .cookie(login_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&PostImageOrder { image_ids: vec![3, 1] }).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let response = client
+            .post("/api/reorder-post-images/1")
+            .cookie(login_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&PostImageOrder { image_ids: vec![3, 1, 2] }).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .post("/api/remove-post-image/1")
+            .cookie(login_cookie.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let mut response = client
+            .get("/api/recent-posts?n=1")
+            .cookie(login_cookie.clone())
+            .dispatch();
+        let posts: Vec<PostDetails> = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(posts[0].image_ids, vec![3, 2]);
+
+        let response = client
+            .get("/api/post-image/1")
+            .cookie(login_cookie.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client
+            .get("/api/post-image/2")
+            .cookie(login_cookie.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_blobs_from_older_database() -> Result<(), Error> {
+        let mut conn = Connection::open_in_memory()?;
+        let now = Utc::now().naive_utc();
+
+        // the tables as older versions of the server created them
+        conn.execute(
+            "CREATE TABLE user (
+                    user_id                 INTEGER PRIMARY KEY,
+                    hash                    TEXT NOT NULL,
+                    email                   TEXT NOT NULL,
+                    created_at              TEXT NOT NULL,
+                    display_name            TEXT NOT NULL,
+                    real_name               TEXT NOT NULL,
+                    profile_pic             BLOB NOT NULL
+                    )",
+            params![],
+        )?;
+        conn.execute(
+            "CREATE TABLE post (
+                    id                      INTEGER PRIMARY KEY,
+                    body                    TEXT NOT NULL,
+                    created_at              TEXT NOT NULL,
+                    user_id                 INTEGER,
+                    image                   BLOB
+                    )",
+            params![],
+        )?;
+        conn.execute(
+            "INSERT INTO user (hash, email, created_at, display_name, real_name, profile_pic)
+                    VALUES ('hash', 'email@gmail.com', ?1, 'name', 'real name', ?2)",
+            params![now, b"profile pic".to_vec()],
+        )?;
+        conn.execute(
+            "INSERT INTO post (body, created_at, user_id, image) VALUES ('with image', ?1, 1, ?2)",
+            params![now, b"post image".to_vec()],
+        )?;
+        conn.execute(
+            "INSERT INTO post (body, created_at, user_id, image) VALUES ('without image', ?1, 1, NULL)",
+            params![now],
+        )?;
+
+        let dir = tempdir()?;
+        let media = MediaStore::new(dir.path())?;
+        init_database(&conn)?;
+        migrate_blobs_to_media_store(&mut conn, &media)?;
+        // running it again should be a no-op
+        migrate_blobs_to_media_store(&mut conn, &media)?;
+
+        assert!(!column_exists(&conn, "user", "profile_pic")?);
+        assert!(!column_exists(&conn, "post", "image")?);
+
+        let mut profile_pic = vec![];
+        media.open(&User::profile_pic_hash(&conn, 1)?)?.read_to_end(&mut profile_pic)?;
+        assert_eq!(profile_pic, b"profile pic".to_vec());
+
+        assert_eq!(Post::load_id(&conn, 2)?.body, "without image");
+        assert!(PostImage::ids_for_post(&conn, 2)?.is_empty());
+
+        let image_ids = PostImage::ids_for_post(&conn, 1)?;
+        assert_eq!(image_ids.len(), 1);
+        let mut image_data = vec![];
+        media.open(&PostImage::load_id(&conn, image_ids[0])?.hash)?.read_to_end(&mut image_data)?;
+        assert_eq!(image_data, b"post image".to_vec());
+
+        Ok(())
+    }
+
+    #[test]
+    fn update_profile_and_profile_pic() -> Result<(), Error> {
+        let (client, _media_dir, _mail_dir) = test_client()?;
+        let db = client.rocket().state::<DbConn>().unwrap();
+        let media = client.rocket().state::<MediaStore>().unwrap();
+        let secret_key = client.rocket().state::<ArgonSecretKey>().unwrap();
+
+        let password = "myAmazingPassw0rd!".to_string();
+        {
+            let conn = db.lock().unwrap();
+            create_dummy_user(&conn, &media, "user_1@gmail.com".to_string(), password.clone(), secret_key.0.clone())?;
+            create_dummy_user(&conn, &media, "user_2@gmail.com".to_string(), password.clone(), secret_key.0.clone())?;
+        }
+        let login_cookie = login(&client, "user_1@gmail.com".to_string(), password).expect("logged in");
+
+        let update = ProfileUpdateInfo {
+            display_name: Some("New Name".to_string()),
+            real_name: None,
+            details: None
+        };
+        let mut response = client
+            .post("/api/update-profile")
+            .cookie(login_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&update).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let user_info: UserInfo = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(user_info.display_name, "New Name");
+        assert_eq!(user_info.real_name, "Some Dummy <user_1@gmail.com>");
+
+        // the real name has to stay unique
+        let update = ProfileUpdateInfo {
+            display_name: None,
+            real_name: Some("Some Dummy <user_2@gmail.com>".to_string()),
+            details: None
+        };
+        let response = client
+            .post("/api/update-profile")
+            .cookie(login_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&update).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+
+        let identicon_hash = User::profile_pic_hash(&db.lock().unwrap(), 1)?;
+
+        let mut file = File::open(concat!(root_dir!(), "/svelte-app/public/favicon.png")).unwrap();
+        let mut favicon_buf = vec![];
+        file.read_to_end(&mut favicon_buf).unwrap();
+
+        let response = client
+            .post("/api/set-profile-pic?x=0&y=0&width=10&height=10")
+            .cookie(login_cookie.clone())
+            .header(ContentType::Binary)
+            .body(&favicon_buf)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let mut response = client
+            .get("/api/profile-pic/1")
+            .cookie(login_cookie.clone())
+            .dispatch();
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("private, no-cache"));
+        let profile_pic = image::load_from_memory(&response.body_bytes().unwrap()).unwrap();
+        assert_eq!(profile_pic.dimensions(), (10, 10));
 
-        let user = User::load_email(&conn, &me.email)?;
-        assert_eq!(&user, &me);
+        let response = client
+            .post("/api/set-profile-pic?x=0&y=0&width=100000&height=10")
+            .cookie(login_cookie.clone())
+            .header(ContentType::Binary)
+            .body(&favicon_buf)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
 
-        let profile_pic = User::get_profile_pic(&conn, 1)?;
-        assert_eq!(&me.profile_pic, &profile_pic);
+        let response = client
+            .post("/api/reset-profile-pic")
+            .cookie(login_cookie.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        // the identicon is generated from the new display name
+        assert_ne!(User::profile_pic_hash(&db.lock().unwrap(), 1)?, identicon_hash);
 
         Ok(())
     }
 
     #[test]
-    fn create_user_from_info() -> Result<(), Error> {
-        let conn = Connection::open_in_memory()?;
-        init_database(&conn)?;
+    fn profile_details_visibility() -> Result<(), Error> {
+        let (client, _media_dir, _mail_dir) = test_client()?;
+        let db = client.rocket().state::<DbConn>().unwrap();
+        let media = client.rocket().state::<MediaStore>().unwrap();
+        let secret_key = client.rocket().state::<ArgonSecretKey>().unwrap();
 
-        let secret_key = "test secret key";
         let password = "myAmazingPassw0rd!".to_string();
-        let email = "some_email@gmail.com".to_string();
-        create_dummy_user(&conn, email.clone(), password.clone(), secret_key.to_string())?;
-
-        let user = User::load_id(&conn, 1)?;
-        assert_eq!(user.auth(&LoginInfo { email: email.clone(), password: password.clone() }, secret_key)?, true);
-        assert_eq!(user.auth(&LoginInfo { email: "adifferentemail@gmail.com".to_string(), password }, secret_key)?, false);
-        assert_eq!(user.auth(&LoginInfo { email: email.clone(), password: "thisisthewrongpassword".to_string() }, secret_key)?, false);
-        assert_eq!(user.auth(&LoginInfo { email: "anotheremail@gmail.com".to_string(), password: "83quhejwdknas".to_string() }, secret_key)?, false);
-
-        Ok(())
-    }
-
-    #[test]
-    fn load_invalid_id() -> Result<(), Error> {
-        let conn = Connection::open_in_memory()?;
-        init_database(&conn)?;
+        {
+            let conn = db.lock().unwrap();
+            create_dummy_user(&conn, &media, "owner@gmail.com".to_string(), password.clone(), secret_key.0.clone())?;
+            create_dummy_user(&conn, &media, "viewer@gmail.com".to_string(), password.clone(), secret_key.0.clone())?;
+        }
+        let owner_cookie = login(&client, "owner@gmail.com".to_string(), password.clone()).expect("logged in");
+        let viewer_cookie = login(&client, "viewer@gmail.com".to_string(), password).expect("logged in");
+
+        let details = ProfileDetails {
+            bio: Some(ProfileField { value: "Hello there".to_string(), visibility: Visibility::Members }),
+            birthday: Some(ProfileField {
+                value: Birthday { year: Some(1990), month: 2, day: 14, hide_year: true },
+                visibility: Visibility::Members
+            }),
+            hometown: Some(ProfileField { value: "Springfield".to_string(), visibility: Visibility::OnlyMe }),
+            pronouns: None,
+            links: Some(ProfileField { value: vec!["https://example.com".to_string()], visibility: Visibility::Members }),
+            cover_photo: None
+        };
+        let update = ProfileUpdateInfo { display_name: None, real_name: None, details: Some(details) };
+        let response = client
+            .post("/api/update-profile")
+            .cookie(owner_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&update).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
 
-        let res = User::load_id(&conn, 1);
+        let mut response = client
+            .get("/api/user-info/1")
+            .cookie(viewer_cookie.clone())
+            .dispatch();
+        let profile: UserProfile = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(profile.info.display_name, "dummy");
+        assert_eq!(profile.details.bio.unwrap().value, "Hello there");
+        assert_eq!(profile.details.birthday.unwrap().value.year, None);
+        assert!(profile.details.hometown.is_none());
 
-        // error should be "QueryReturnedNoRows"
-        // TODO: re-add this more precise test with some sort of assert_matches
-        // assert_eq!(res, Err(Error::DatabaseErr(rusqlite::Error::QueryReturnedNoRows)));
-        assert!(res.is_err());
-        Ok(())
-    }
+        let mut response = client
+            .get("/api/user-info/1")
+            .cookie(owner_cookie.clone())
+            .dispatch();
+        let profile: UserProfile = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(profile.details.birthday.unwrap().value.year, Some(1990));
+        assert_eq!(profile.details.hometown.unwrap().value, "Springfield");
 
-    #[test]
-    fn create_with_existing_email() -> Result<(), Error> {
-        let conn = Connection::open_in_memory()?;
-        init_database(&conn)?;
-        let secret_key = "test key";
-        let email = "anemail@gmail.com".to_string();
+        let mut file = File::open(concat!(root_dir!(), "/svelte-app/public/favicon.png")).unwrap();
+        let mut favicon_buf = vec![];
+        file.read_to_end(&mut favicon_buf).unwrap();
 
-        let register_info_1 = RegisterInfo {
-            email: email.clone(),
-            password: "aofsaff".to_string(),
-            display_name: "name".to_string(),
-            real_name: "realname".to_string()
-        };
+        let response = client
+            .post("/api/set-cover-photo")
+            .cookie(owner_cookie.clone())
+            .header(ContentType::Binary)
+            .body(&favicon_buf)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
 
-        let register_info_2 = RegisterInfo {
-            email: email.clone(),
-            password: "lmzioiofa".to_string(),
-            display_name: "name2".to_string(),
-            real_name: "realname2".to_string()
-        };
+        // hide the cover photo from everyone else
+        let details = ProfileDetails { cover_photo: Some(Visibility::OnlyMe), ..ProfileDetails::default() };
+        let update = ProfileUpdateInfo { display_name: None, real_name: None, details: Some(details) };
+        client
+            .post("/api/update-profile")
+            .cookie(owner_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&update).unwrap())
+            .dispatch();
 
-        User::create_new(&conn, &register_info_1, secret_key)?;
-        assert!(User::create_new(&conn, &register_info_2, secret_key).is_err());
+        let response = client.get("/api/cover-photo/1").cookie(owner_cookie.clone()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.get("/api/cover-photo/1").cookie(viewer_cookie.clone()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
 
         Ok(())
     }
 
     #[test]
-    fn create_with_existing_real_name() -> Result<(), Error> {
-        let conn = Connection::open_in_memory()?;
-        init_database(&conn)?;
-        let secret_key = "test key";
-        let real_name = "arealname".to_string();
-
-        let register_info_1 = RegisterInfo {
-            email: "someemail@gmail.com".to_string(),
-            password: "aofsaff".to_string(),
-            display_name: "name".to_string(),
-            real_name: real_name.clone()
+    fn invite_only_signup() -> Result<(), Error> {
+        let (client, _media_dir, _mail_dir) = test_client()?;
+
+        let signup = |email: &str, invite_code: Option<String>| {
+            let reg_info = RegisterInfo {
+                email: email.to_string(),
+                password: "myAmazingPassw0rd!".to_string(),
+                display_name: "dummy".to_string(),
+                real_name: email.to_string(),
+                invite_code
+            };
+
+            client.post("/api/signup")
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(&reg_info).unwrap())
+                .dispatch()
         };
 
-        let register_info_2 = RegisterInfo {
-            email: "someotheremail@gmail.com".to_string(),
-            password: "lmzioiofa".to_string(),
-            display_name: "name2".to_string(),
-            real_name: real_name.clone()
-        };
+        // the first user doesn't need an invite and becomes the admin
+        let response = signup("admin@gmail.com", None);
+        assert_eq!(response.status(), Status::Created);
+        let admin_cookie = session_cookie(&response).expect("logged in");
 
-        User::create_new(&conn, &register_info_1, secret_key)?;
-        assert!(User::create_new(&conn, &register_info_2, secret_key).is_err());
+        assert_eq!(signup("user_1@gmail.com", None).status(), Status::BadRequest);
+        assert_eq!(signup("user_1@gmail.com", Some("notarealcode".to_string())).status(), Status::BadRequest);
 
-        Ok(())
-    }
+        let info = InviteCreationInfo { expires_in_hours: Some(24), max_uses: Some(1) };
+        let mut response = client
+            .post("/api/admin/create-invite")
+            .cookie(admin_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&info).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let invite: InviteCreationResponse = serde_json::from_str(&response.body_string().unwrap()).unwrap();
 
-    #[test]
-    fn create_with_existing_real_name_and_email() -> Result<(), Error> {
-        let conn = Connection::open_in_memory()?;
-        init_database(&conn)?;
-        let secret_key = "test key";
-        let real_name = "arealname".to_string();
-        let email = "someemail@gmail.com".to_string();
+        let response = signup("user_1@gmail.com", Some(invite.code.clone()));
+        assert_eq!(response.status(), Status::Created);
+        let user_cookie = session_cookie(&response).expect("logged in");
 
-        let register_info_1 = RegisterInfo {
-            email: email.clone(),
-            password: "aofsaff".to_string(),
-            display_name: "name".to_string(),
-            real_name: real_name.clone()
-        };
+        // the invite was only good for one use
+        assert_eq!(signup("user_2@gmail.com", Some(invite.code)).status(), Status::BadRequest);
 
-        let register_info_2 = RegisterInfo {
-            email: email.clone(),
-            password: "lmzioiofa".to_string(),
-            display_name: "name2".to_string(),
-            real_name: real_name.clone()
-        };
+        // only admins can manage invites
+        let response = client
+            .post("/api/admin/create-invite")
+            .cookie(user_cookie)
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&info).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let info = InviteCreationInfo { expires_in_hours: None, max_uses: None };
+        let mut response = client
+            .post("/api/admin/create-invite")
+            .cookie(admin_cookie.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&info).unwrap())
+            .dispatch();
+        let invite: InviteCreationResponse = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        let response = client
+            .post(format!("/api/admin/revoke-invite/{}", invite.code))
+            .cookie(admin_cookie.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(signup("user_2@gmail.com", Some(invite.code)).status(), Status::BadRequest);
 
-        User::create_new(&conn, &register_info_1, secret_key)?;
-        assert!(User::create_new(&conn, &register_info_2, secret_key).is_err());
+        let mut response = client
+            .get("/api/admin/invites")
+            .cookie(admin_cookie)
+            .dispatch();
+        let invites: Vec<InviteDetails> = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(invites.len(), 2);
+        assert!(invites.iter().any(|invite| invite.revoked));
+        assert!(invites.iter().any(|invite| invite.uses == 1));
 
         Ok(())
     }
 
     #[test]
-    fn create_post_set_image() -> Result<(), Error> {
-        let conn = Connection::open_in_memory()?;
-        init_database(&conn)?;
-        let schema = init_search_schema();
-
-        let client = Client::new(rocket(conn, Index::create_in_ram(schema.clone()), schema)?).unwrap();
+    fn sessions_can_be_revoked() -> Result<(), Error> {
+        let (client, _media_dir, _mail_dir) = test_client()?;
         let db = client.rocket().state::<DbConn>().unwrap();
+        let media = client.rocket().state::<MediaStore>().unwrap();
         let secret_key = client.rocket().state::<ArgonSecretKey>().unwrap();
 
         let email = "some_email@gmail.com".to_string();
         let password = "myAmazingPassw0rd!".to_string();
         {
             let conn = db.lock().unwrap();
-            create_dummy_user(&conn, email.clone(), password.clone(), secret_key.0.clone())?;
+            create_dummy_user(&conn, &media, email.clone(), password.clone(), secret_key.0.clone())?;
         }
-        let login_cookie = login(&client, email, password).expect("logged in");
-
-        let post_info = PostInfo {
-            body: "This is the body of a post!".to_string()
-        };
+        let laptop_cookie = login(&client, email.clone(), password.clone()).expect("logged in");
+        let phone_cookie = login(&client, email.clone(), password.clone()).expect("logged in");
 
         let mut response = client
-            .post("/api/create-post")
-            .cookie(login_cookie.clone())
-            .header(ContentType::JSON)
-            .body(serde_json::to_string(&post_info).unwrap())
+            .get("/api/sessions")
+            .cookie(laptop_cookie.clone())
             .dispatch();
-        assert_eq!(response.status(), Status::Created);
-        let response_json = response.body_string().unwrap();
-        let created_post: PostCreationResponse = serde_json::from_str(&response_json).unwrap();
-        assert_eq!(created_post.post_id, 1);
+        let sessions: Vec<SessionDetails> = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions.iter().filter(|s| s.current).count(), 1);
+        let phone_session = sessions.iter().find(|s| !s.current).unwrap();
 
-        {
-            // doing this in a new block so the mutex lock drops and allows the
-            // second post to succeed
-            let conn = db.lock().unwrap();
-            let post = Post::load_id(&conn, 1)?;
-            assert!(post.image.is_none());
-            assert_eq!(&post.body, &post_info.body);
-            assert_eq!(post.user_id, 1);
-        }
+        let response = client
+            .post(format!("/api/revoke-session/{}", phone_session.id))
+            .cookie(laptop_cookie.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
 
-        let mut file = File::open(concat!(root_dir!(), "/svelte-app/public/favicon.png")).unwrap();
-        let mut favicon_buf = vec![];
-        file.read_to_end(&mut favicon_buf).unwrap();
+        let response = client.get("/api/users").cookie(phone_cookie).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.get("/api/users").cookie(laptop_cookie.clone()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
 
+        // logging out everywhere includes the session doing it
         let response = client
-            .post("/api/set-post-image/1")
-            .cookie(login_cookie.clone())
-            .header(ContentType::Binary)
-            .body(&favicon_buf)
+            .post("/api/revoke-all-sessions")
+            .cookie(laptop_cookie.clone())
             .dispatch();
         assert_eq!(response.status(), Status::Ok);
+        let response = client.get("/api/users").cookie(laptop_cookie).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
 
-        let conn = db.lock().unwrap();
-        let post = Post::load_id(&conn, 1)?;
-
-        assert!(post.image.is_some());
-        // The image gets upscaled and re-encoded to JPEG by the server, so it
-        // should be significantly larger now
-        assert!(post.image.unwrap().len() > favicon_buf.len());
+        // sessions that go unused for too long expire
+        let idle_cookie = login(&client, email, password).expect("logged in");
+        {
+            let conn = db.lock().unwrap();
+            let long_ago = Utc::now().naive_utc() - chrono::Duration::days(365);
+            conn.execute("UPDATE session SET last_seen_at=?1", params![long_ago])?;
+        }
+        let response = client.get("/api/users").cookie(idle_cookie).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
 
         Ok(())
     }
 
     #[test]
-    fn search_posts() -> Result<(), Error> {
-        let conn = Connection::open_in_memory()?;
-        init_database(&conn)?;
-        let schema = init_search_schema();
-
-        let client = Client::new(rocket(conn, Index::create_in_ram(schema.clone()), schema)?).unwrap();
+    fn change_and_reset_password() -> Result<(), Error> {
+        let (client, _media_dir, mail_dir) = test_client()?;
         let db = client.rocket().state::<DbConn>().unwrap();
+        let media = client.rocket().state::<MediaStore>().unwrap();
         let secret_key = client.rocket().state::<ArgonSecretKey>().unwrap();
 
         let email = "some_email@gmail.com".to_string();
         let password = "myAmazingPassw0rd!".to_string();
         {
             let conn = db.lock().unwrap();
-            create_dummy_user(&conn, email.clone(), password.clone(), secret_key.0.clone())?;
+            create_dummy_user(&conn, &media, email.clone(), password.clone(), secret_key.0.clone())?;
         }
-        let login_cookie = login(&client, email, password).expect("logged in");
+        let laptop_cookie = login(&client, email.clone(), password.clone()).expect("logged in");
+        let phone_cookie = login(&client, email.clone(), password.clone()).expect("logged in");
 
-        let body_1 = "Unicorns are amazing!!!".to_string();
-        let body_2 = "Pink is the best color on the planet".to_string();
-        let body_3 = "Rokt leeg is best video game".to_string();
-        let body_4 = "Bunnies are pretty cool too I guess".to_string();
-        let body_5 = "But did I mention unicorns are amazing?!?!?!?".to_string();
+        let change_password = |old_password: &str, new_password: &str| {
+            let change_info = PasswordChangeInfo {
+                old_password: old_password.to_string(),
+                new_password: new_password.to_string()
+            };
 
-        let post_infos = vec![
-            PostInfo {
-                body: body_1.clone()
-            },
-            PostInfo {
-                body: body_2.clone()
-            },
-            PostInfo {
-                body: body_3.clone()
-            },
-            PostInfo {
-                body: body_4.clone()
-            },
-            PostInfo {
-                body: body_5.clone()
-            }
-        ];
+            client.post("/api/change-password")
+                .cookie(laptop_cookie.clone())
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(&change_info).unwrap())
+                .dispatch()
+                .status()
+        };
 
-        for post_info in post_infos {
-            let response = client
-                .post("/api/create-post")
-                .cookie(login_cookie.clone())
+        assert_eq!(change_password("notmypassword", "newPassw0rd!"), Status::Unauthorized);
+        assert_eq!(change_password(&password, "newPassw0rd!"), Status::Ok);
+
+        // the other session was ended but the one that changed the password wasn't
+        assert_eq!(client.get("/api/users").cookie(phone_cookie).dispatch().status(), Status::NotFound);
+        assert_eq!(client.get("/api/users").cookie(laptop_cookie.clone()).dispatch().status(), Status::Ok);
+        assert!(login(&client, email.clone(), password).is_none());
+        assert!(login(&client, email.clone(), "newPassw0rd!".to_string()).is_some());
+
+        let request_reset = |email: &str| {
+            client.post("/api/request-password-reset")
                 .header(ContentType::JSON)
-                .body(serde_json::to_string(&post_info).unwrap())
-                .dispatch();
-                assert_eq!(response.status(), Status::Created);
-        }
+                .body(serde_json::to_string(&PasswordResetRequest { email: email.to_string() }).unwrap())
+                .dispatch()
+                .status()
+        };
 
-        // TODO: for some reason tantivy seems to not have fully committed
-        // documents even after the call to commit() returns, despite the call
-        // stating that it is blocking and that all documents are fully
-        // committed when it returns. I'm getting non-deterministic results
-        // when running search queries immediately after commits on arch linux.
-        //
-        // this sleep is a stop-gap for now, I should probably investigate
-        // and report this issue?
-        std::thread::sleep(std::time::Duration::from_millis(10));
+        // nobody can tell whether an account exists
+        assert_eq!(request_reset("nobody@gmail.com"), Status::Ok);
+        assert_eq!(std::fs::read_dir(mail_dir.path()).map(|dir| dir.count()).unwrap_or(0), 0);
 
-        let mut response = client
-            .get("/api/search-posts?query_string=unicorns")
-            .cookie(login_cookie.clone())
-            .dispatch();
+        assert_eq!(request_reset(&email), Status::Ok);
+        let emails = std::fs::read_dir(mail_dir.path())?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(emails.len(), 1);
+        let mut email_text = String::new();
+        File::open(emails[0].path())?.read_to_string(&mut email_text)?;
+        let token = email_text.split("token=").nth(1).unwrap().split_whitespace().next().unwrap();
 
-        let response_json = response.body_string().unwrap();
-        let search_results: Vec<PostDetails> = serde_json::from_str(&response_json).unwrap();
+        let reset_password = |token: &str| {
+            let reset_info = PasswordResetInfo {
+                token: token.to_string(),
+                new_password: "resetPassw0rd!".to_string()
+            };
 
-        assert!(
-            &search_results[0].body == &body_1 ||
-            &search_results[1].body == &body_1
-        );
+            client.post("/api/reset-password")
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(&reset_info).unwrap())
+                .dispatch()
+                .status()
+        };
 
-        assert!(
-            &search_results[0].body == &body_5 ||
-            &search_results[1].body == &body_5
-        );
+        assert_eq!(reset_password("notarealtoken"), Status::BadRequest);
+        assert_eq!(reset_password(token), Status::Ok);
+        // tokens only work once
+        assert_eq!(reset_password(token), Status::BadRequest);
 
-        let mut response = client
-            .get("/api/search-posts?query_string=rokt+leeg")
-            .cookie(login_cookie.clone())
-            .dispatch();
+        assert_eq!(client.get("/api/users").cookie(laptop_cookie).dispatch().status(), Status::NotFound);
+        assert!(login(&client, email, "resetPassw0rd!".to_string()).is_some());
 
-        let response_json = response.body_string().unwrap();
-        let search_results: Vec<PostDetails> = serde_json::from_str(&response_json).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn login_does_not_reveal_accounts() -> Result<(), Error> {
+        let (client, _media_dir, _mail_dir) = test_client()?;
+        let db = client.rocket().state::<DbConn>().unwrap();
+        let media = client.rocket().state::<MediaStore>().unwrap();
+        let secret_key = client.rocket().state::<ArgonSecretKey>().unwrap();
+
+        let email = "some_email@gmail.com".to_string();
+        {
+            let conn = db.lock().unwrap();
+            create_dummy_user(&conn, &media, email.clone(), "myAmazingPassw0rd!".to_string(), secret_key.0.clone())?;
+        }
+
+        let attempt_login = |email: &str| {
+            let login_info = LoginInfo {
+                email: email.to_string(),
+                password: "thisisthewrongpassword".to_string()
+            };
+
+            let mut response = client.post("/api/login")
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(&login_info).unwrap())
+                .dispatch();
+
+            (response.status(), response.body_string())
+        };
 
-        assert!(&search_results[0].body == &body_3);
+        let wrong_password = attempt_login(&email);
+        assert_eq!(wrong_password.0, Status::Unauthorized);
+        assert_eq!(attempt_login("nobody@gmail.com"), wrong_password);
 
         Ok(())
     }
@@ -0,0 +1,592 @@
+//! Content-addressed storage for uploaded media.
+//!
+//! Files are kept on disk under the media root, named after the SHA-256 hash
+//! of their contents. Only metadata about the files lives in the database.
+//!
+//! Uploaded images are kept as-is alongside a set of resized renditions, which
+//! are what actually gets served to clients.
+
+use crate::Error;
+use crate::metadata::{self, ExifInfo};
+
+use chrono::{NaiveDateTime, Utc};
+use image::{guess_format, load_from_memory, ColorType, FilterType, GenericImageView, ImageFormat, ImageOutputFormat};
+use rocket::http::{ContentType, RawStr, Status};
+use rocket::request::{FromFormValue, Request};
+use rocket::response::{self, Responder, Response};
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Returns the hex-encoded SHA-256 hash of the given data
+fn hash(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Returns the media type of the given data, as best as can be told from its
+/// contents
+fn sniff_content_type(data: &[u8]) -> &'static str {
+    match guess_format(data) {
+        Ok(ImageFormat::PNG) => "image/png",
+        Ok(ImageFormat::JPEG) => "image/jpeg",
+        Ok(ImageFormat::GIF) => "image/gif",
+        Ok(ImageFormat::WEBP) => "image/webp",
+        Ok(ImageFormat::BMP) => "image/bmp",
+        Ok(ImageFormat::TIFF) => "image/tiff",
+        Ok(ImageFormat::ICO) => "image/x-icon",
+        _ => "application/octet-stream"
+    }
+}
+
+/// Returns true if images of the given color type can be transparent
+fn has_alpha(color: ColorType) -> bool {
+    match color {
+        ColorType::GrayA(_) | ColorType::RGBA(_) | ColorType::BGRA(_) => true,
+        _ => false
+    }
+}
+
+/// The sizes that images are made available in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum RenditionSize {
+    /// Small enough for avatars and previews
+    Thumbnail,
+    /// Sized for display inline in the feed
+    Feed,
+    /// Sized for full-screen viewing
+    Full
+}
+
+impl RenditionSize {
+    /// Every size, from smallest to largest
+    pub(crate) const ALL: [RenditionSize; 3] = [RenditionSize::Thumbnail, RenditionSize::Feed, RenditionSize::Full];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            RenditionSize::Thumbnail => "thumbnail",
+            RenditionSize::Feed => "feed",
+            RenditionSize::Full => "full"
+        }
+    }
+
+    /// The largest width or height an image of this size may have
+    pub(crate) fn max_dimension(self) -> u32 {
+        match self {
+            RenditionSize::Thumbnail => 160,
+            RenditionSize::Feed => 800,
+            RenditionSize::Full => 2048
+        }
+    }
+}
+
+impl<'v> FromFormValue<'v> for RenditionSize {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<Self, Self::Error> {
+        RenditionSize::ALL.iter()
+            .cloned()
+            .find(|size| size.as_str() == form_value.as_str())
+            .ok_or(form_value)
+    }
+}
+
+/// A region of an image to keep, in pixels of the image as it is displayed
+#[derive(FromForm)]
+pub(crate) struct Crop {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32
+}
+
+/// An uploaded image along with its renditions, ready to be stored.
+///
+/// Building one of these is relatively expensive and doesn't require access to
+/// the database, so it should be done before taking the database lock.
+pub(crate) struct ProcessedImage {
+    original: Vec<u8>,
+    renditions: Vec<(RenditionSize, Vec<u8>)>,
+    taken_at: Option<NaiveDateTime>
+}
+
+impl ProcessedImage {
+    /// Decodes the given image data and renders it at every `RenditionSize`.
+    ///
+    /// Images are only ever scaled down, never up, and are turned the right way
+    /// up according to their EXIF orientation. PNG, JPEG and GIF images keep
+    /// their format. Anything else is converted to PNG if it can be transparent
+    /// and JPEG otherwise.
+    ///
+    /// Metadata is stripped from everything, including the kept original.
+    pub(crate) fn new(upload: Vec<u8>) -> Result<Self, Error> {
+        ProcessedImage::process(upload, None)
+    }
+
+    /// Like `new`, but only keeps the given region of the image.
+    ///
+    /// The cropped image is kept as a lossless original in place of the upload.
+    pub(crate) fn cropped(upload: Vec<u8>, crop: &Crop) -> Result<Self, Error> {
+        ProcessedImage::process(upload, Some(crop))
+    }
+
+    fn process(upload: Vec<u8>, crop: Option<&Crop>) -> Result<Self, Error> {
+        let input_format = guess_format(&upload).map_err(|_| Error::ImageUploadFailed)?;
+        let exif = ExifInfo::read(&upload);
+        let image = load_from_memory(&upload).map_err(|_| Error::ImageUploadFailed)?;
+        let mut image = metadata::apply_orientation(image, exif.orientation);
+
+        if let Some(crop) = crop {
+            let fits = crop.width > 0 && crop.height > 0 &&
+                crop.x.checked_add(crop.width).map_or(false, |right| right <= image.width()) &&
+                crop.y.checked_add(crop.height).map_or(false, |bottom| bottom <= image.height());
+
+            if !fits {
+                return Err(Error::InvalidInput("the crop region must be within the image"));
+            }
+
+            image = image.crop(crop.x, crop.y, crop.width, crop.height);
+        }
+
+        // the image crate can't encode WebP, so those get converted along with
+        // the other formats browsers don't all support
+        let output_format = match input_format {
+            ImageFormat::PNG => ImageOutputFormat::PNG,
+            ImageFormat::JPEG => ImageOutputFormat::JPEG(90),
+            ImageFormat::GIF => ImageOutputFormat::GIF,
+            _ if has_alpha(image.color()) => ImageOutputFormat::PNG,
+            _ => ImageOutputFormat::JPEG(90)
+        };
+
+        let original = match input_format {
+            // the cropped image is all that's needed to re-process it later
+            _ if crop.is_some() => {
+                let mut data = vec![];
+                image.write_to(&mut data, ImageOutputFormat::PNG).map_err(|_| Error::ImageUploadFailed)?;
+                data
+            },
+            ImageFormat::JPEG => metadata::strip_jpeg_metadata(&upload, exif.orientation)?,
+            ImageFormat::PNG => metadata::strip_png_metadata(&upload)?,
+            ImageFormat::GIF => metadata::strip_gif_metadata(&upload)?,
+            // there's no telling where metadata might be hiding in other formats,
+            // so they're kept as a lossless re-encoding instead
+            _ => {
+                let mut data = vec![];
+                image.write_to(&mut data, ImageOutputFormat::PNG).map_err(|_| Error::ImageUploadFailed)?;
+                data
+            }
+        };
+
+        let mut renditions = vec![];
+
+        for &size in RenditionSize::ALL.iter() {
+            let max = size.max_dimension();
+            let mut data = vec![];
+
+            let written = if image.width() > max || image.height() > max {
+                image.resize(max, max, FilterType::CatmullRom).write_to(&mut data, output_format.clone())
+            } else if input_format == ImageFormat::GIF && crop.is_none() {
+                // re-encoding would only keep the first frame of an animation
+                data = original.clone();
+                Ok(())
+            } else {
+                image.write_to(&mut data, output_format.clone())
+            };
+            written.map_err(|_| Error::ImageUploadFailed)?;
+
+            renditions.push((size, data));
+        }
+
+        Ok(ProcessedImage { original, renditions, taken_at: exif.taken_at })
+    }
+
+    /// When the image was taken according to its EXIF data, if it says
+    pub(crate) fn taken_at(&self) -> Option<NaiveDateTime> {
+        self.taken_at
+    }
+}
+
+/// A stored file, served with the headers browsers need to cache it.
+///
+/// Stored files never change, so browsers may keep them forever and the hash
+/// makes for a strong ETag. Requests that already have the file get a 304.
+pub(crate) struct CachedFile {
+    content_type: ContentType,
+    hash: String,
+    file: File,
+    /// Whether browsers need to check that the file is still current before
+    /// using their copy
+    revalidate: bool
+}
+
+impl CachedFile {
+    /// Makes browsers check with the server before using their copy of the file.
+    ///
+    /// Needed when the same URL can end up serving a different file.
+    pub(crate) fn always_revalidate(mut self) -> Self {
+        self.revalidate = true;
+        self
+    }
+}
+
+impl<'r> Responder<'r> for CachedFile {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let etag = format!("\"{}\"", self.hash);
+        let already_cached = request.headers()
+            .get("If-None-Match")
+            .flat_map(|value| value.split(','))
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+
+        // images are only served to logged in users, so shared caches shouldn't
+        // keep them
+        let cache_control = if self.revalidate {
+            "private, no-cache"
+        } else {
+            "private, max-age=31536000, immutable"
+        };
+
+        let mut response = Response::build();
+        response
+            .raw_header("ETag", etag)
+            .raw_header("Cache-Control", cache_control);
+
+        if already_cached {
+            response.status(Status::NotModified);
+        } else {
+            response.header(self.content_type).sized_body(self.file);
+        }
+
+        response.ok()
+    }
+}
+
+/// Stores files on disk by the hash of their contents.
+///
+/// Storing the same bytes twice only results in one file on disk. The `media`
+/// table keeps track of how many things refer to each file so that it can be
+/// removed once nothing does.
+pub(crate) struct MediaStore {
+    root: PathBuf
+}
+
+impl MediaStore {
+    /// Creates a media store that keeps its files in the given directory,
+    /// creating the directory if it does not already exist.
+    pub(crate) fn new<P: AsRef<Path>>(root: P) -> Result<Self, Error> {
+        fs::create_dir_all(root.as_ref())?;
+
+        Ok(MediaStore {
+            root: root.as_ref().to_path_buf()
+        })
+    }
+
+    /// Creates a table in the given database for storing media metadata.
+    ///
+    /// The table will only be created if it does not already exist.
+    pub(crate) fn create_table(conn: &Connection) -> Result<(), Error> {
+        conn.execute(
+            "CREATE TABLE if not exists media (
+                    hash                    TEXT PRIMARY KEY,
+                    size                    INTEGER NOT NULL,
+                    refs                    INTEGER NOT NULL,
+                    created_at              TEXT NOT NULL,
+                    content_type            TEXT
+                    )",
+            params![],
+        )?;
+        crate::add_column_if_missing(conn, "media", "content_type", "TEXT")?;
+
+        Ok(conn.execute(
+            "CREATE TABLE if not exists rendition (
+                    original_hash           TEXT NOT NULL,
+                    size                    TEXT NOT NULL,
+                    hash                    TEXT NOT NULL,
+                    PRIMARY KEY (original_hash, size)
+                    )",
+            params![],
+        ).map(|_| ())?)
+    }
+
+    /// Returns the path that the file with the given hash is stored at.
+    ///
+    /// Files are spread out across two levels of directories named after the
+    /// start of their hash so that no single directory gets too large.
+    fn path(&self, hash: &str) -> PathBuf {
+        self.root.join(&hash[0..2]).join(&hash[2..4]).join(hash)
+    }
+
+    /// Stores the given data and returns its hash.
+    ///
+    /// Every call adds a reference to the stored file which should be given
+    /// back with `release` once it is no longer needed.
+    pub(crate) fn store(&self, conn: &Connection, data: &[u8]) -> Result<String, Error> {
+        let hash = hash(data);
+        let path = self.path(&hash);
+
+        if !path.exists() {
+            fs::create_dir_all(path.parent().unwrap())?;
+
+            // writing to a temporary file first means a partially written file
+            // never shows up under the real name
+            let tmp_path = path.with_extension("tmp");
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(data)?;
+            file.sync_all()?;
+            fs::rename(&tmp_path, &path)?;
+        }
+
+        let updated = conn.execute("UPDATE media SET refs=refs+1 WHERE hash=?1", params![hash])?;
+        if updated == 0 {
+            conn.execute(
+                "INSERT INTO media (hash, size, refs, created_at, content_type)
+                        VALUES (?1, ?2, 1, ?3, ?4)",
+                params![hash, data.len() as i64, Utc::now().naive_utc(), sniff_content_type(data)],
+            )?;
+        }
+
+        Ok(hash)
+    }
+
+    /// Stores the given image and its renditions, returning the hash of the
+    /// original.
+    ///
+    /// The renditions belong to the original and are released along with it.
+    pub(crate) fn store_image(&self, conn: &Connection, image: &ProcessedImage) -> Result<String, Error> {
+        let hash = self.store(conn, &image.original)?;
+
+        if !self.has_renditions(conn, &hash)? {
+            self.add_renditions(conn, &hash, image)?;
+        }
+
+        Ok(hash)
+    }
+
+    /// Returns true if renditions have been stored for the original with the
+    /// given hash.
+    pub(crate) fn has_renditions(&self, conn: &Connection, original_hash: &str) -> Result<bool, Error> {
+        let mut stmt = conn.prepare("SELECT * FROM rendition WHERE original_hash=?1")?;
+        Ok(stmt.exists(params![original_hash])?)
+    }
+
+    /// Stores the renditions of the given image as belonging to the original
+    /// with the given hash.
+    pub(crate) fn add_renditions(&self, conn: &Connection, original_hash: &str, image: &ProcessedImage) -> Result<(), Error> {
+        for (size, data) in &image.renditions {
+            // a rendition can come out identical to the original, in which case
+            // it must not hold a reference to it or the original would never
+            // be released
+            let hash = if hash(data) == original_hash {
+                original_hash.to_string()
+            } else {
+                self.store(conn, data)?
+            };
+
+            conn.execute(
+                "INSERT INTO rendition (original_hash, size, hash) VALUES (?1, ?2, ?3)",
+                params![original_hash, size.as_str(), hash],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the hash of the given size rendition of the original with the
+    /// given hash.
+    pub(crate) fn rendition_hash(&self, conn: &Connection, original_hash: &str, size: RenditionSize) -> Result<String, Error> {
+        Ok(conn.query_row(
+            "SELECT hash FROM rendition WHERE original_hash=?1 AND size=?2",
+            params![original_hash, size.as_str()],
+            |row| row.get(0)
+        )?)
+    }
+
+    /// Returns the media type of the file with the given hash.
+    pub(crate) fn content_type(&self, conn: &Connection, hash: &str) -> Result<ContentType, Error> {
+        let content_type: String = conn.query_row(
+            "SELECT content_type FROM media WHERE hash=?1",
+            params![hash],
+            |row| row.get(0)
+        )?;
+
+        Ok(ContentType::parse_flexible(&content_type).unwrap_or(ContentType::Binary))
+    }
+
+    /// Records the media type of any files stored before media types were
+    /// tracked.
+    pub(crate) fn fill_missing_content_types(&self, conn: &Connection) -> Result<(), Error> {
+        let hashes = {
+            let mut stmt = conn.prepare("SELECT hash FROM media WHERE content_type IS NULL")?;
+            let hash_iter = stmt.query_map(params![], |row| row.get::<_, String>(0))?;
+            hash_iter.collect::<Result<Vec<_>, _>>()?
+        };
+
+        for hash in hashes {
+            let data = fs::read(self.path(&hash))?;
+            conn.execute(
+                "UPDATE media SET content_type=?1 WHERE hash=?2",
+                params![sniff_content_type(&data), hash],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Opens the file with the given hash for reading.
+    pub(crate) fn open(&self, hash: &str) -> Result<File, Error> {
+        Ok(File::open(self.path(hash))?)
+    }
+
+    /// Opens the file with the given hash for serving with caching headers.
+    pub(crate) fn open_cached(&self, content_type: ContentType, hash: String) -> Result<CachedFile, Error> {
+        Ok(CachedFile {
+            content_type,
+            file: self.open(&hash)?,
+            hash,
+            revalidate: false
+        })
+    }
+
+    /// Gives back a reference to the file with the given hash.
+    ///
+    /// The file is deleted once nothing refers to it anymore, along with any
+    /// renditions of it.
+    pub(crate) fn release(&self, conn: &Connection, hash: &str) -> Result<(), Error> {
+        conn.execute("UPDATE media SET refs=refs-1 WHERE hash=?1", params![hash])?;
+        let refs: Option<i64> = conn.query_row(
+            "SELECT refs FROM media WHERE hash=?1",
+            params![hash],
+            |row| row.get(0)
+        ).optional()?;
+
+        if refs.map_or(false, |refs| refs <= 0) {
+            conn.execute("DELETE FROM media WHERE hash=?1", params![hash])?;
+
+            if let Err(e) = fs::remove_file(self.path(hash)) {
+                if e.kind() != ErrorKind::NotFound {
+                    return Err(e.into());
+                }
+            }
+
+            let rendition_hashes = {
+                let mut stmt = conn.prepare("SELECT hash FROM rendition WHERE original_hash=?1")?;
+                let hash_iter = stmt.query_map(params![hash], |row| row.get::<_, String>(0))?;
+                hash_iter.collect::<Result<Vec<_>, _>>()?
+            };
+
+            conn.execute("DELETE FROM rendition WHERE original_hash=?1", params![hash])?;
+            for rendition_hash in rendition_hashes.iter().filter(|h| *h != hash) {
+                self.release(conn, rendition_hash)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn identical_data_is_stored_once() -> Result<(), Error> {
+        let conn = Connection::open_in_memory()?;
+        MediaStore::create_table(&conn)?;
+        let dir = tempdir()?;
+        let media = MediaStore::new(dir.path())?;
+
+        let hash_1 = media.store(&conn, b"some image data")?;
+        let hash_2 = media.store(&conn, b"some image data")?;
+        let hash_3 = media.store(&conn, b"some other image data")?;
+        assert_eq!(hash_1, hash_2);
+        assert_ne!(hash_1, hash_3);
+        assert_eq!(fs::read(media.path(&hash_1))?, b"some image data".to_vec());
+
+        // the file should stick around until every reference is given back
+        media.release(&conn, &hash_1)?;
+        assert!(media.path(&hash_1).exists());
+        media.release(&conn, &hash_1)?;
+        assert!(!media.path(&hash_1).exists());
+        assert!(media.path(&hash_3).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn renditions_are_never_upscaled() -> Result<(), Error> {
+        let conn = Connection::open_in_memory()?;
+        MediaStore::create_table(&conn)?;
+        let dir = tempdir()?;
+        let media = MediaStore::new(dir.path())?;
+
+        let mut data = vec![];
+        image::DynamicImage::new_rgb8(1000, 500)
+            .write_to(&mut data, ImageOutputFormat::PNG)
+            .map_err(|_| Error::ImageUploadFailed)?;
+
+        let hash = media.store_image(&conn, &ProcessedImage::new(data.clone())?)?;
+        assert_eq!(fs::read(media.path(&hash))?, data);
+
+        let dimensions = |size| -> Result<(u32, u32), Error> {
+            let rendition = fs::read(media.path(&media.rendition_hash(&conn, &hash, size)?))?;
+            Ok(load_from_memory(&rendition).map_err(|_| Error::ImageUploadFailed)?.dimensions())
+        };
+        assert_eq!(dimensions(RenditionSize::Thumbnail)?, (160, 80));
+        assert_eq!(dimensions(RenditionSize::Feed)?, (800, 400));
+        assert_eq!(dimensions(RenditionSize::Full)?, (1000, 500));
+
+        // renditions go away along with the original
+        let thumbnail_hash = media.rendition_hash(&conn, &hash, RenditionSize::Thumbnail)?;
+        media.release(&conn, &hash)?;
+        assert!(!media.path(&thumbnail_hash).exists());
+        assert!(media.rendition_hash(&conn, &hash, RenditionSize::Thumbnail).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn renditions_keep_the_uploaded_format() -> Result<(), Error> {
+        let conn = Connection::open_in_memory()?;
+        MediaStore::create_table(&conn)?;
+        let dir = tempdir()?;
+        let media = MediaStore::new(dir.path())?;
+
+        let rendition_content_type = |image: image::DynamicImage, format| -> Result<ContentType, Error> {
+            let mut data = vec![];
+            image.write_to(&mut data, format).map_err(|_| Error::ImageUploadFailed)?;
+            let hash = media.store_image(&conn, &ProcessedImage::new(data)?)?;
+            media.content_type(&conn, &media.rendition_hash(&conn, &hash, RenditionSize::Feed)?)
+        };
+
+        assert_eq!(rendition_content_type(image::DynamicImage::new_rgba8(10, 10), ImageOutputFormat::PNG)?, ContentType::PNG);
+        assert_eq!(rendition_content_type(image::DynamicImage::new_rgb8(10, 10), ImageOutputFormat::JPEG(90))?, ContentType::JPEG);
+        assert_eq!(rendition_content_type(image::DynamicImage::new_rgb8(10, 10), ImageOutputFormat::GIF)?, ContentType::GIF);
+        // formats browsers can't all display get converted
+        assert_eq!(rendition_content_type(image::DynamicImage::new_rgb8(10, 10), ImageOutputFormat::BMP)?, ContentType::JPEG);
+
+        Ok(())
+    }
+
+    #[test]
+    fn exif_orientation_is_applied() -> Result<(), Error> {
+        let mut jpeg = vec![];
+        image::DynamicImage::new_rgb8(20, 10)
+            .write_to(&mut jpeg, ImageOutputFormat::JPEG(90))
+            .map_err(|_| Error::ImageUploadFailed)?;
+        // rotated 90 degrees clockwise, as a phone held upright would say
+        let jpeg = metadata::strip_jpeg_metadata(&jpeg, 6)?;
+
+        let image = ProcessedImage::new(jpeg)?;
+        assert_eq!(ExifInfo::read(&image.original).orientation, 6);
+
+        let (_, feed) = &image.renditions[1];
+        let feed = load_from_memory(feed).map_err(|_| Error::ImageUploadFailed)?;
+        assert_eq!(feed.dimensions(), (10, 20));
+        assert_eq!(ExifInfo::read(&image.renditions[1].1).orientation, 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,370 @@
+//! Handling of the metadata embedded in uploaded images.
+//!
+//! Photos straight off a phone carry EXIF data recording where they were taken
+//! and with what device. None of that should ever be stored, so it gets
+//! stripped before anything is written to the media store. The only things
+//! kept are the orientation, which is needed to display the image the right
+//! way up, and optionally the time the photo was taken.
+
+use crate::Error;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use exif::{In, Tag, Value};
+use image::DynamicImage;
+
+use std::io::Cursor;
+
+/// The bits of EXIF data that are worth keeping
+pub(crate) struct ExifInfo {
+    /// The EXIF orientation of the image, from 1 to 8
+    pub(crate) orientation: u16,
+    /// When the photo was taken, in whatever timezone the camera was set to
+    pub(crate) taken_at: Option<NaiveDateTime>
+}
+
+impl ExifInfo {
+    /// Reads what's worth keeping from the EXIF data in the given image, if it
+    /// has any.
+    pub(crate) fn read(data: &[u8]) -> Self {
+        let exif = match exif::Reader::new().read_from_container(&mut Cursor::new(data)) {
+            Ok(exif) => exif,
+            Err(_) => return ExifInfo { orientation: 1, taken_at: None }
+        };
+
+        let orientation = exif.get_field(Tag::Orientation, In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0))
+            .filter(|&orientation| orientation >= 1 && orientation <= 8)
+            .unwrap_or(1) as u16;
+
+        let taken_at = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)
+            .and_then(|field| match field.value {
+                Value::Ascii(ref values) if !values.is_empty() => exif::DateTime::from_ascii(&values[0]).ok(),
+                _ => None
+            })
+            .and_then(|t| {
+                NaiveDate::from_ymd_opt(t.year as i32, t.month as u32, t.day as u32)?
+                    .and_hms_opt(t.hour as u32, t.minute as u32, t.second as u32)
+            });
+
+        ExifInfo { orientation, taken_at }
+    }
+}
+
+/// Transforms the given image so that it displays the right way up without
+/// needing the given EXIF orientation.
+pub(crate) fn apply_orientation(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image
+    }
+}
+
+/// Builds a JPEG APP1 segment containing EXIF data with nothing but the given
+/// orientation.
+fn orientation_segment(orientation: u16) -> Vec<u8> {
+    let mut payload = b"Exif\0\0".to_vec();
+    // big endian TIFF header with the first IFD right after it
+    payload.extend_from_slice(&[b'M', b'M', 0, 42, 0, 0, 0, 8]);
+    // a single entry: the orientation as one SHORT, padded to four bytes
+    payload.extend_from_slice(&[0, 1, 0x01, 0x12, 0, 3, 0, 0, 0, 1]);
+    payload.extend_from_slice(&orientation.to_be_bytes());
+    payload.extend_from_slice(&[0, 0]);
+    // no further IFDs
+    payload.extend_from_slice(&[0, 0, 0, 0]);
+
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    segment.extend_from_slice(&payload);
+    segment
+}
+
+/// Returns true if the JPEG segment with the given marker and payload should
+/// be kept.
+///
+/// The JFIF header, ICC color profiles and Adobe color transform information
+/// affect how the image looks. Everything else, including EXIF, XMP, IPTC,
+/// comments and MPF indexes of extra images, is dropped.
+fn keep_jpeg_segment(marker: u8, payload: &[u8]) -> bool {
+    match marker {
+        0xE0 => true,
+        0xE2 => payload.starts_with(b"ICC_PROFILE\0"),
+        0xEE => payload.starts_with(b"Adobe"),
+        0xE1..=0xEF | 0xFE => false,
+        _ => true
+    }
+}
+
+/// Returns the position of the first marker after the entropy-coded data
+/// starting at the given position in a JPEG image.
+///
+/// Stuffed zero bytes and restart markers are part of the data.
+fn skip_entropy_coded_data(data: &[u8], mut pos: usize) -> usize {
+    while pos + 1 < data.len() {
+        match (data[pos], data[pos + 1]) {
+            (0xFF, 0x00) | (0xFF, 0xD0..=0xD7) => pos += 2,
+            (0xFF, _) => return pos,
+            _ => pos += 1
+        }
+    }
+
+    data.len()
+}
+
+/// Removes all metadata from the given JPEG image, leaving only the given EXIF
+/// orientation.
+///
+/// Anything after the end of the image is dropped too, since phones put extra
+/// images and videos there.
+pub(crate) fn strip_jpeg_metadata(data: &[u8], orientation: u16) -> Result<Vec<u8>, Error> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err(Error::ImageUploadFailed);
+    }
+
+    let mut stripped = vec![0xFF, 0xD8];
+    let mut wrote_orientation = orientation == 1;
+    let mut pos = 2;
+
+    loop {
+        // markers may be preceded by any number of fill bytes
+        while data.get(pos) == Some(&0xFF) && data.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+
+        let marker = match (data.get(pos), data.get(pos + 1)) {
+            (Some(&0xFF), Some(&marker)) => marker,
+            _ => return Err(Error::ImageUploadFailed)
+        };
+
+        // the orientation goes right after the JFIF header if there is one
+        if !wrote_orientation && marker != 0xE0 {
+            stripped.extend_from_slice(&orientation_segment(orientation));
+            wrote_orientation = true;
+        }
+
+        if marker == 0xD9 {
+            stripped.extend_from_slice(&[0xFF, 0xD9]);
+            return Ok(stripped);
+        }
+
+        // markers without a length
+        if marker == 0x01 || (marker >= 0xD0 && marker <= 0xD7) {
+            stripped.extend_from_slice(&data[pos..pos + 2]);
+            pos += 2;
+            continue;
+        }
+
+        let len = match (data.get(pos + 2), data.get(pos + 3)) {
+            (Some(&high), Some(&low)) => u16::from_be_bytes([high, low]) as usize,
+            _ => return Err(Error::ImageUploadFailed)
+        };
+        let end = pos + 2 + len;
+        if len < 2 || end > data.len() {
+            return Err(Error::ImageUploadFailed);
+        }
+
+        if keep_jpeg_segment(marker, &data[pos + 4..end]) {
+            stripped.extend_from_slice(&data[pos..end]);
+        }
+
+        pos = end;
+
+        // the start of scan is followed by the image data, and progressive
+        // images have several scans
+        if marker == 0xDA {
+            pos = skip_entropy_coded_data(data, pos);
+            stripped.extend_from_slice(&data[end..pos]);
+
+            // a truncated image still gets an end
+            if pos >= data.len() {
+                stripped.extend_from_slice(&[0xFF, 0xD9]);
+                return Ok(stripped);
+            }
+        }
+    }
+}
+
+/// Ancillary PNG chunks that affect how the image looks
+const KEPT_PNG_CHUNKS: [&[u8]; 13] = [
+    b"tRNS", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"bKGD",
+    b"hIST", b"pHYs", b"sPLT", b"acTL", b"fcTL", b"fdAT"
+];
+
+/// Removes all metadata from the given PNG image.
+///
+/// Text chunks, timestamps and EXIF data are dropped. Critical chunks and the
+/// chunks needed to display the image (including animation) are kept.
+pub(crate) fn strip_png_metadata(data: &[u8]) -> Result<Vec<u8>, Error> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    if !data.starts_with(SIGNATURE) {
+        return Err(Error::ImageUploadFailed);
+    }
+
+    let mut stripped = SIGNATURE.to_vec();
+    let mut pos = SIGNATURE.len();
+
+    while pos < data.len() {
+        if pos + 8 > data.len() {
+            return Err(Error::ImageUploadFailed);
+        }
+
+        let len = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let chunk_type = &data[pos + 4..pos + 8];
+        // length, type, data and CRC
+        let end = pos + 12 + len;
+        if end > data.len() {
+            return Err(Error::ImageUploadFailed);
+        }
+
+        // chunks with an uppercase first letter are critical and can't be
+        // dropped
+        let critical = chunk_type[0].is_ascii_uppercase();
+        if critical || KEPT_PNG_CHUNKS.contains(&chunk_type) {
+            stripped.extend_from_slice(&data[pos..end]);
+        }
+
+        pos = end;
+    }
+
+    Ok(stripped)
+}
+
+/// Returns the position just past the data sub-blocks starting at the given
+/// position in a GIF image.
+fn skip_gif_sub_blocks(data: &[u8], mut pos: usize) -> Result<usize, Error> {
+    loop {
+        let len = *data.get(pos).ok_or(Error::ImageUploadFailed)? as usize;
+        pos += 1 + len;
+
+        if len == 0 {
+            return Ok(pos);
+        }
+    }
+}
+
+/// Removes all metadata from the given GIF image.
+///
+/// Comments and application extensions (which is where XMP data lives) are
+/// dropped, except for the one that makes animations loop.
+pub(crate) fn strip_gif_metadata(data: &[u8]) -> Result<Vec<u8>, Error> {
+    // header and logical screen descriptor
+    if data.len() < 13 || !data.starts_with(b"GIF") {
+        return Err(Error::ImageUploadFailed);
+    }
+
+    let mut pos = 13;
+    // global color table
+    if data[10] & 0x80 != 0 {
+        pos += 3 << ((data[10] & 0x07) + 1);
+    }
+
+    let mut stripped = data.get(..pos).ok_or(Error::ImageUploadFailed)?.to_vec();
+
+    loop {
+        let start = pos;
+        match data.get(pos) {
+            // extension
+            Some(0x21) => {
+                let label = *data.get(pos + 1).ok_or(Error::ImageUploadFailed)?;
+                pos = skip_gif_sub_blocks(data, pos + 2)?;
+
+                let keep = match label {
+                    0xFE => false,
+                    0xFF => data.get(start + 3..start + 14) == Some(&b"NETSCAPE2.0"[..]),
+                    _ => true
+                };
+                if keep {
+                    stripped.extend_from_slice(data.get(start..pos).ok_or(Error::ImageUploadFailed)?);
+                }
+            },
+            // image descriptor, optional local color table and image data
+            Some(0x2C) => {
+                let flags = *data.get(pos + 9).ok_or(Error::ImageUploadFailed)?;
+                pos += 10;
+                if flags & 0x80 != 0 {
+                    pos += 3 << ((flags & 0x07) + 1);
+                }
+                // skip the LZW minimum code size
+                pos = skip_gif_sub_blocks(data, pos + 1)?;
+                stripped.extend_from_slice(data.get(start..pos).ok_or(Error::ImageUploadFailed)?);
+            },
+            // trailer
+            Some(0x3B) => {
+                stripped.push(0x3B);
+                return Ok(stripped);
+            },
+            _ => return Err(Error::ImageUploadFailed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn jpeg_metadata_is_replaced_with_orientation() -> Result<(), Error> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        // JFIF header
+        jpeg.extend_from_slice(&[0xFF, 0xE0, 0, 7, b'J', b'F', b'I', b'F', 0]);
+        // EXIF data that would have held the location
+        jpeg.extend_from_slice(&[0xFF, 0xE1, 0, 8, b'E', b'x', b'i', b'f', 0, 0]);
+        // a comment
+        jpeg.extend_from_slice(&[0xFF, 0xFE, 0, 4, b'h', b'i']);
+        // start of scan followed by the image data
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0, 2, 1, 2, 3, 0xFF, 0xD9]);
+
+        let stripped = strip_jpeg_metadata(&jpeg, 6)?;
+
+        let mut expected = vec![0xFF, 0xD8, 0xFF, 0xE0, 0, 7, b'J', b'F', b'I', b'F', 0];
+        expected.extend_from_slice(&orientation_segment(6));
+        expected.extend_from_slice(&[0xFF, 0xDA, 0, 2, 1, 2, 3, 0xFF, 0xD9]);
+        assert_eq!(stripped, expected);
+        assert_eq!(ExifInfo::read(&stripped).orientation, 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn jpeg_trailing_data_is_dropped() -> Result<(), Error> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        // two scans, with a stuffed byte and a restart marker in the data
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0, 2, 1, 0xFF, 0x00, 2, 0xFF, 0xD0, 3]);
+        jpeg.extend_from_slice(&[0xFF, 0xC4, 0, 3, 4]);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0, 2, 5, 6, 0xFF, 0xD9]);
+        let image_len = jpeg.len();
+        // a second image, like the ones MPF points to, then a video
+        jpeg.extend_from_slice(&[0xFF, 0xD8, 0xFF, 0xE1, 0, 4, 1, 2, 0xFF, 0xD9]);
+        jpeg.extend_from_slice(b"\0\0\0\x18ftypmp42");
+
+        assert_eq!(strip_jpeg_metadata(&jpeg, 1)?, &jpeg[..image_len]);
+
+        // a truncated image is ended where it stops
+        let truncated = [0xFF, 0xD8, 0xFF, 0xDA, 0, 2, 1, 2];
+        assert_eq!(strip_jpeg_metadata(&truncated, 1)?, vec![0xFF, 0xD8, 0xFF, 0xDA, 0, 2, 1, 2, 0xFF, 0xD9]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn jpeg_mpf_index_is_dropped() -> Result<(), Error> {
+        let icc = [0xFF, 0xE2, 0, 14, b'I', b'C', b'C', b'_', b'P', b'R', b'O', b'F', b'I', b'L', b'E', 0];
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend_from_slice(&icc);
+        jpeg.extend_from_slice(&[0xFF, 0xE2, 0, 10, b'M', b'P', b'F', 0, b'M', b'M', 0, 42]);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0, 2, 1, 0xFF, 0xD9]);
+
+        let mut expected = vec![0xFF, 0xD8];
+        expected.extend_from_slice(&icc);
+        expected.extend_from_slice(&[0xFF, 0xDA, 0, 2, 1, 0xFF, 0xD9]);
+        assert_eq!(strip_jpeg_metadata(&jpeg, 1)?, expected);
+
+        Ok(())
+    }
+}
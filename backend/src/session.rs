@@ -0,0 +1,228 @@
+//! Server-side login sessions.
+//!
+//! Logging in creates a session identified by a random token that is handed to
+//! the browser in a private cookie. Only a hash of the token is kept in the
+//! database, so a leaked database can't be used to log in as anyone.
+
+use crate::{generate_token, DbConn, Error};
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use rocket::http::{Cookie, Cookies};
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use rocket::State;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The name of the cookie holding the session token
+pub(crate) const SESSION_COOKIE: &str = "session";
+
+/// How long sessions last
+pub(crate) struct SessionConfig {
+    /// How long a session can go unused before it expires
+    pub(crate) idle_timeout: Duration,
+    /// How long a session lasts no matter how often it's used
+    pub(crate) max_age: Duration
+}
+
+/// Details about the client making a request, recorded against sessions so
+/// users can tell them apart
+pub(crate) struct ClientInfo {
+    ip: Option<String>,
+    user_agent: Option<String>
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientInfo {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ClientInfo, Self::Error> {
+        Outcome::Success(ClientInfo {
+            ip: request.client_ip().map(|ip| ip.to_string()),
+            user_agent: request.headers().get_one("User-Agent").map(|ua| ua.to_string())
+        })
+    }
+}
+
+/// Returns the hash of the given token that is stored in the database
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// A logged in session that hasn't expired
+#[derive(Clone, Debug)]
+pub(crate) struct Session {
+    pub(crate) id: u32,
+    pub(crate) user_id: u32
+}
+
+/// Web client receives this when listing the user's sessions
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SessionDetails {
+    pub(crate) id: u32,
+    pub(crate) created_at: i64,
+    pub(crate) last_seen_at: i64,
+    pub(crate) ip: Option<String>,
+    pub(crate) user_agent: Option<String>,
+    /// Whether this is the session the list was requested with
+    pub(crate) current: bool
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Session {
+    type Error = Error;
+
+    /// Looks up the session for the token in the request's session cookie.
+    ///
+    /// The result is cached so that a request only looks up its session once.
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Session, Self::Error> {
+        let session = request.local_cache(|| {
+            let db = request.guard::<State<DbConn>>().unwrap();
+            let config = request.guard::<State<SessionConfig>>().unwrap();
+            let client = request.guard::<ClientInfo>().unwrap();
+            let token = request.cookies()
+                .get_private(SESSION_COOKIE)
+                .map(|cookie| cookie.value().to_string())?;
+
+            let conn = db.lock().unwrap();
+            match Session::from_token(&conn, &token, &config, &client) {
+                Ok(session) => session,
+                Err(e) => {
+                    error!("Error while loading session: {:?}", e);
+                    None
+                }
+            }
+        });
+
+        match session {
+            Some(session) => Outcome::Success(session.clone()),
+            None => Outcome::Forward(())
+        }
+    }
+}
+
+impl Session {
+    /// Creates a table in the given database for storing sessions.
+    ///
+    /// The table will only be created if it does not already exist.
+    pub(crate) fn create_table(conn: &Connection) -> Result<(), Error> {
+        Ok(conn.execute(
+            "CREATE TABLE if not exists session (
+                    id                      INTEGER PRIMARY KEY,
+                    token_hash              TEXT NOT NULL UNIQUE,
+                    user_id                 INTEGER NOT NULL,
+                    created_at              TEXT NOT NULL,
+                    last_seen_at            TEXT NOT NULL,
+                    ip                      TEXT,
+                    user_agent              TEXT
+                    )",
+            params![],
+        ).map(|_| ())?)
+    }
+
+    /// Starts a new session for the given user and hands its token to the
+    /// client in the session cookie.
+    pub(crate) fn start(
+        cookies: &mut Cookies,
+        conn: &Connection,
+        config: &SessionConfig,
+        user_id: u32,
+        client: &ClientInfo
+    ) -> Result<(), Error> {
+        let token = generate_token(32);
+        let now = Utc::now().naive_utc();
+
+        conn.execute(
+            "INSERT INTO session (token_hash, user_id, created_at, last_seen_at, ip, user_agent)
+                    VALUES (?1, ?2, ?3, ?3, ?4, ?5)",
+            params![hash_token(&token), user_id, now, client.ip, client.user_agent],
+        )?;
+
+        // rocket would otherwise expire the cookie after a week
+        let mut cookie = Cookie::new(SESSION_COOKIE, token);
+        cookie.set_max_age(config.max_age);
+
+        // TODO: set the secure flag on this cookie when not in dev mode
+        cookies.add_private(cookie);
+        Ok(())
+    }
+
+    /// Loads the session with the given token, recording that it was just used
+    /// by the given client.
+    ///
+    /// Returns `None` if there is no such session or it has expired.
+    fn from_token(conn: &Connection, token: &str, config: &SessionConfig, client: &ClientInfo) -> Result<Option<Self>, Error> {
+        let row: Option<(u32, u32, NaiveDateTime, NaiveDateTime)> = conn.query_row(
+            "SELECT id, user_id, created_at, last_seen_at FROM session WHERE token_hash=?1",
+            params![hash_token(token)],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        ).optional()?;
+
+        let (id, user_id, created_at, last_seen_at) = match row {
+            Some(row) => row,
+            None => return Ok(None)
+        };
+
+        let now = Utc::now().naive_utc();
+        if now - last_seen_at > config.idle_timeout || now - created_at > config.max_age {
+            conn.execute("DELETE FROM session WHERE id=?1", params![id])?;
+            return Ok(None);
+        }
+
+        conn.execute(
+            "UPDATE session SET last_seen_at=?1, ip=?2, user_agent=?3 WHERE id=?4",
+            params![now, client.ip, client.user_agent, id],
+        )?;
+
+        Ok(Some(Session { id, user_id }))
+    }
+
+    /// Loads the details of all of the given user's sessions, most recently
+    /// used first.
+    ///
+    /// `current_id` is the id of the session making the request.
+    pub(crate) fn load_all(conn: &Connection, user_id: u32, current_id: u32) -> Result<Vec<SessionDetails>, Error> {
+        let mut stmt = conn.prepare(
+            "SELECT id, created_at, last_seen_at, ip, user_agent FROM session
+                WHERE user_id=?1 ORDER BY last_seen_at DESC"
+        )?;
+        let session_iter = stmt.query_map(params![user_id], |row| {
+            let id = row.get(0)?;
+
+            Ok(SessionDetails {
+                id,
+                created_at: row.get::<_, NaiveDateTime>(1)?.timestamp(),
+                last_seen_at: row.get::<_, NaiveDateTime>(2)?.timestamp(),
+                ip: row.get(3)?,
+                user_agent: row.get(4)?,
+                current: id == current_id
+            })
+        })?;
+
+        session_iter.map(|res| match res {
+            Ok(v) => Ok(v),
+            Err(e) => Err(Error::from(e))
+        }).collect()
+    }
+
+    /// Ends the session with the given id if it belongs to the given user
+    pub(crate) fn revoke(conn: &Connection, user_id: u32, session_id: u32) -> Result<(), Error> {
+        match conn.execute("DELETE FROM session WHERE id=?1 AND user_id=?2", params![session_id, user_id])? {
+            0 => Err(Error::NotFound),
+            _ => Ok(())
+        }
+    }
+
+    /// Ends every session belonging to the given user except the one with the
+    /// given id
+    pub(crate) fn revoke_others(conn: &Connection, user_id: u32, keep_id: u32) -> Result<(), Error> {
+        Ok(conn.execute(
+            "DELETE FROM session WHERE user_id=?1 AND id!=?2",
+            params![user_id, keep_id],
+        ).map(|_| ())?)
+    }
+
+    /// Ends every session belonging to the given user
+    pub(crate) fn revoke_all(conn: &Connection, user_id: u32) -> Result<(), Error> {
+        Ok(conn.execute("DELETE FROM session WHERE user_id=?1", params![user_id]).map(|_| ())?)
+    }
+}